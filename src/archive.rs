@@ -0,0 +1,72 @@
+//! Zip archives of the selected files, for `--archive`.
+
+use crate::{entry_path, render_sections, Config, Section};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Seek, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Name of the document holding the tree and prompt inside an archive.
+pub const ARCHIVE_DOCUMENT: &str = "promptify.md";
+
+/// Writes the files of `sections` into a zip archive as they are on disk,
+/// under their displayed paths, along with a [`ARCHIVE_DOCUMENT`] holding the
+/// trees and the prompt. Files that could not be read are left out. Returns
+/// how many files were archived.
+///
+/// Paths leading out of the directory, as `--relative-to` can give, lose
+/// their `..` parts. A name that is already taken then gets a number, as in
+/// `a.rs (2)`, so no entry replaces another.
+pub fn write_archive(
+    config: &Config,
+    sections: &[Section],
+    writer: impl Write + Seek,
+) -> io::Result<usize> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(writer);
+
+    let document_config = Config {
+        format: crate::Format::Markdown,
+        tree_only: true,
+        ..config.clone()
+    };
+    zip.start_file(ARCHIVE_DOCUMENT, options)?;
+    zip.write_all(render_sections(&document_config, sections).as_bytes())?;
+
+    let mut taken = HashSet::from([ARCHIVE_DOCUMENT.to_string()]);
+    let mut archived = 0;
+    for section in sections {
+        for file in section.files.iter().filter(|file| file.content.is_some()) {
+            let Ok(bytes) = fs::read(&file.path) else {
+                continue;
+            };
+            let name = entry_name_in(
+                &entry_path(config, sections, section, &file.path),
+                &mut taken,
+            );
+            zip.start_file(name, options)?;
+            zip.write_all(&bytes)?;
+            archived += 1;
+        }
+    }
+    zip.finish()?;
+    Ok(archived)
+}
+
+/// `path` as the name of a new entry, kept inside the archive and numbered
+/// when an earlier entry has the name already. Adds it to `taken`.
+fn entry_name_in(path: &str, taken: &mut HashSet<String>) -> String {
+    let name = path
+        .split(['/', '\\'])
+        .filter(|part| !matches!(*part, "" | "." | ".."))
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut unique = name.clone();
+    let mut count = 1;
+    while !taken.insert(unique.clone()) {
+        count += 1;
+        unique = format!("{} ({})", name, count);
+    }
+    unique
+}
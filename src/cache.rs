@@ -0,0 +1,61 @@
+//! On-disk cache of file contents for `--cache`.
+//!
+//! Each file read is stored in its own cache file, named by a hash of its
+//! absolute path and the encoding handling, and headed by the size and
+//! modification time it had. A later run reuses the stored contents while
+//! both match, so only changed files are read again. Deleting the cache
+//! directory at any time is safe.
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{self, Path};
+use std::process;
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Returns the cached contents of `path`, or reads them with `read` and
+/// stores them for the next run. Failing to use the cache is never an
+/// error, the file is just read as usual.
+pub(crate) fn read_cached(
+    cache_dir: &Path,
+    path: &Path,
+    metadata: Option<&Metadata>,
+    variant: &str,
+    read: impl FnOnce() -> io::Result<Option<String>>,
+) -> io::Result<Option<String>> {
+    let (Some(metadata), Ok(absolute)) = (metadata, path::absolute(path)) else {
+        return read();
+    };
+    let key = format!("{}\0{}", absolute.to_string_lossy(), variant);
+    let entry = cache_dir.join(format!("{:016x}", xxh3_64(key.as_bytes())));
+    let header = header(metadata);
+
+    if let Ok(cached) = fs::read_to_string(&entry) {
+        if let Some(content) = cached.strip_prefix(&header) {
+            return Ok(Some(content.to_string()));
+        }
+    }
+    let content = read()?;
+    if let Some(content) = &content {
+        // Best effort, through a temporary file so readers never see half an entry
+        let temp = entry.with_extension(format!("{}.tmp", process::id()));
+        let stored = fs::create_dir_all(cache_dir)
+            .and_then(|()| fs::write(&temp, format!("{}{}", header, content)))
+            .and_then(|()| fs::rename(&temp, &entry));
+        if stored.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+    }
+    Ok(content)
+}
+
+/// The first line of a cache entry: the size and modification time the file
+/// had when it was stored.
+fn header(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    format!("{} {}\n", metadata.len(), modified)
+}
@@ -0,0 +1,414 @@
+//! Conservative comment removal for `--strip-comments`.
+//!
+//! Each supported language family gets a minimal lexer that knows just
+//! enough about string literals not to mistake their contents for comments.
+//! Anything it does not understand is left alone. The same lexer finds the
+//! comments and strings that `--format html` highlights.
+
+use std::ops::Range;
+
+/// Comment and string syntax of a language family.
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_blocks: bool,
+    quotes: &'static [char],
+    /// Whether `'` strings support backslash escapes.
+    single_quote_escapes: bool,
+    /// Python/TOML style `"""` and `'''` strings.
+    triple_quotes: bool,
+    /// Rust raw strings, and `'` as a char literal only when it looks like one
+    /// (otherwise it is a lifetime).
+    rust: bool,
+    /// Comments and strings only start at the beginning of a word, as in
+    /// shell scripts and YAML (`$#`, `don't`).
+    word_start: bool,
+    /// JavaScript regex literals, which may contain `//` or `/*`.
+    regex_literals: bool,
+}
+
+impl Syntax {
+    fn for_language(language: &str) -> Option<Syntax> {
+        const C_LIKE: Syntax = Syntax {
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            nested_blocks: false,
+            quotes: &['"', '\''],
+            single_quote_escapes: true,
+            triple_quotes: false,
+            rust: false,
+            word_start: false,
+            regex_literals: false,
+        };
+        const HASH: Syntax = Syntax {
+            line_comments: &["#"],
+            block_comment: None,
+            nested_blocks: false,
+            quotes: &['"', '\''],
+            single_quote_escapes: true,
+            triple_quotes: false,
+            rust: false,
+            word_start: false,
+            regex_literals: false,
+        };
+
+        match language {
+            "rust" => Some(Syntax {
+                nested_blocks: true,
+                quotes: &['"'],
+                rust: true,
+                ..C_LIKE
+            }),
+            "c" | "cpp" | "java" | "php" => Some(C_LIKE),
+            "javascript" | "typescript" => Some(Syntax {
+                quotes: &['"', '\'', '`'],
+                regex_literals: true,
+                ..C_LIKE
+            }),
+            "go" => Some(Syntax {
+                quotes: &['"', '\'', '`'],
+                ..C_LIKE
+            }),
+            // `//` is not a comment in CSS and unquoted in `url(http://...)` in SCSS
+            "css" | "scss" => Some(Syntax {
+                line_comments: &[],
+                ..C_LIKE
+            }),
+            "python" => Some(Syntax {
+                triple_quotes: true,
+                ..HASH
+            }),
+            "ruby" => Some(HASH),
+            "toml" => Some(Syntax {
+                single_quote_escapes: false,
+                triple_quotes: true,
+                ..HASH
+            }),
+            "bash" | "yaml" | "dotenv" => Some(Syntax {
+                single_quote_escapes: false,
+                word_start: true,
+                ..HASH
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Removes comments from `content`, or returns `None` when `language` is not
+/// supported. Lines left empty by a removed comment are dropped.
+pub(crate) fn strip_comments(content: &str, language: &str) -> Option<String> {
+    let syntax = Syntax::for_language(language)?;
+    let mut stripper = Stripper::new(&syntax, content);
+    stripper.run();
+    Some(stripper.finish())
+}
+
+/// A comment or string literal found by the lexer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Literal {
+    Comment,
+    String,
+}
+
+/// The comments and string literals of `content`, as ranges of character
+/// (not byte) offsets in order, or `None` when `language` is not supported.
+pub(crate) fn literal_spans(content: &str, language: &str) -> Option<Vec<(Range<usize>, Literal)>> {
+    let syntax = Syntax::for_language(language)?;
+    let mut stripper = Stripper::new(&syntax, content);
+    stripper.run();
+    Some(stripper.spans)
+}
+
+struct Stripper<'a> {
+    syntax: &'a Syntax,
+    chars: Vec<char>,
+    pos: usize,
+    /// Finished lines, and whether a comment was removed from them.
+    lines: Vec<(String, bool)>,
+    line: String,
+    stripped: bool,
+    /// The comments and strings met so far.
+    spans: Vec<(Range<usize>, Literal)>,
+}
+
+impl<'a> Stripper<'a> {
+    fn new(syntax: &'a Syntax, content: &str) -> Self {
+        Stripper {
+            syntax,
+            chars: content.chars().collect(),
+            pos: 0,
+            lines: vec![],
+            line: String::new(),
+            stripped: false,
+            spans: vec![],
+        }
+    }
+
+    fn run(&mut self) {
+        // Keep shebangs, which look like comments to the hash family
+        if self.starts_with("#!") {
+            self.copy_until_newline();
+        }
+
+        while self.pos < self.chars.len() {
+            let c = self.chars[self.pos];
+            let start = self.pos;
+            let literal = if self.at_line_comment() {
+                self.skip_line_comment();
+                Some(Literal::Comment)
+            } else if let Some((open, close)) = self.at_block_comment() {
+                self.skip_block_comment(open, close);
+                Some(Literal::Comment)
+            } else if let Some(quote) = self.at_triple_quote() {
+                self.copy_string(&quote, quote.starts_with('"'));
+                Some(Literal::String)
+            } else if self.syntax.rust && c == 'r' && self.at_raw_string() {
+                self.copy_raw_string();
+                Some(Literal::String)
+            } else if self.syntax.regex_literals && c == '/' && self.at_regex() {
+                self.copy_regex();
+                Some(Literal::String)
+            } else if self.syntax.rust && c == '\'' {
+                // Lifetimes only move past their `'`
+                self.copy_rust_quote();
+                (self.pos > start + 1).then_some(Literal::String)
+            } else if self.syntax.quotes.contains(&c) && self.at_word_start() {
+                let escapes = c != '\'' || self.syntax.single_quote_escapes;
+                self.copy_string(&c.to_string(), escapes);
+                Some(Literal::String)
+            } else {
+                self.emit(c);
+                self.pos += 1;
+                None
+            };
+            if let Some(literal) = literal {
+                self.spans.push((start..self.pos, literal));
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.lines
+            .push((std::mem::take(&mut self.line), self.stripped));
+        let mut lines = vec![];
+        for (line, stripped) in &self.lines {
+            if !stripped {
+                lines.push(line.as_str());
+            } else if !line.trim().is_empty() {
+                lines.push(line.trim_end());
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn emit(&mut self, c: char) {
+        if c == '\n' {
+            let line = std::mem::take(&mut self.line);
+            self.lines.push((line, self.stripped));
+            self.stripped = false;
+        } else {
+            self.line.push(c);
+        }
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn at_word_start(&self) -> bool {
+        !self.syntax.word_start
+            || self
+                .line
+                .chars()
+                .last()
+                .is_none_or(|c| c.is_whitespace() || "([{,=:;|&".contains(c))
+    }
+
+    fn at_line_comment(&self) -> bool {
+        self.syntax
+            .line_comments
+            .iter()
+            .any(|start| self.starts_with(start))
+            && self.at_word_start()
+            && !self.in_parameter_expansion()
+    }
+
+    /// Whether a `#` follows `$` or `${`, as in the shell expansions `$#` and
+    /// `${#array[@]}`, which are no comments.
+    fn in_parameter_expansion(&self) -> bool {
+        let before = &self.chars[..self.pos];
+        self.syntax.word_start && (before.ends_with(&['$']) || before.ends_with(&['$', '{']))
+    }
+
+    fn at_block_comment(&self) -> Option<(&'static str, &'static str)> {
+        self.syntax
+            .block_comment
+            .filter(|(open, _)| self.starts_with(open))
+    }
+
+    fn at_triple_quote(&self) -> Option<String> {
+        if !self.syntax.triple_quotes {
+            return None;
+        }
+        ["\"\"\"", "'''"]
+            .into_iter()
+            .find(|quote| self.starts_with(quote))
+            .map(str::to_string)
+    }
+
+    fn skip_line_comment(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+            self.pos += 1;
+        }
+        self.stripped = true;
+    }
+
+    fn skip_block_comment(&mut self, open: &str, close: &str) {
+        let mut depth = 0;
+        while self.pos < self.chars.len() {
+            if self.starts_with(open) && (depth == 0 || self.syntax.nested_blocks) {
+                depth += 1;
+                self.pos += open.len();
+            } else if self.starts_with(close) {
+                depth -= 1;
+                self.pos += close.len();
+                if depth == 0 {
+                    break;
+                }
+            } else {
+                self.pos += 1;
+            }
+        }
+        self.stripped = true;
+    }
+
+    fn copy_until_newline(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+            self.emit(self.chars[self.pos]);
+            self.pos += 1;
+        }
+    }
+
+    /// Copies a string delimited by `quote` verbatim, up to the end of the
+    /// content if it is never closed.
+    fn copy_string(&mut self, quote: &str, escapes: bool) {
+        for c in quote.chars() {
+            self.emit(c);
+        }
+        self.pos += quote.chars().count();
+
+        while self.pos < self.chars.len() {
+            if self.starts_with(quote) {
+                for c in quote.chars() {
+                    self.emit(c);
+                }
+                self.pos += quote.chars().count();
+                return;
+            }
+            let c = self.chars[self.pos];
+            self.emit(c);
+            self.pos += 1;
+            if escapes && c == '\\' {
+                if let Some(&escaped) = self.chars.get(self.pos) {
+                    self.emit(escaped);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// `r"..."` or `r#"..."#` not preceded by an identifier character.
+    fn at_raw_string(&self) -> bool {
+        let after_ident = self.pos > 0 && {
+            let prev = self.chars[self.pos - 1];
+            prev.is_alphanumeric() || prev == '_'
+        };
+        let mut pos = self.pos + 1;
+        while self.chars.get(pos) == Some(&'#') {
+            pos += 1;
+        }
+        !after_ident && self.chars.get(pos) == Some(&'"')
+    }
+
+    fn copy_raw_string(&mut self) {
+        self.emit('r');
+        self.pos += 1;
+        let mut hashes = 0;
+        while self.chars[self.pos] == '#' {
+            self.emit('#');
+            self.pos += 1;
+            hashes += 1;
+        }
+        let close = format!("\"{}", "#".repeat(hashes));
+        self.emit('"');
+        self.pos += 1;
+        while self.pos < self.chars.len() {
+            if self.starts_with(&close) {
+                for c in close.chars() {
+                    self.emit(c);
+                }
+                self.pos += close.len();
+                return;
+            }
+            self.emit(self.chars[self.pos]);
+            self.pos += 1;
+        }
+    }
+
+    /// A `/` that cannot be division because no operand precedes it.
+    fn at_regex(&self) -> bool {
+        let next = self.chars.get(self.pos + 1);
+        next != Some(&'/')
+            && next != Some(&'*')
+            && self
+                .line
+                .trim_end()
+                .chars()
+                .last()
+                .is_none_or(|c| "(,=:[!&|?{};".contains(c))
+    }
+
+    /// Copies a regex literal up to its closing `/`, which may not be escaped
+    /// or inside a character class.
+    fn copy_regex(&mut self) {
+        self.emit('/');
+        self.pos += 1;
+        let mut in_class = false;
+        while self.pos < self.chars.len() {
+            let c = self.chars[self.pos];
+            if c == '\n' {
+                return;
+            }
+            self.emit(c);
+            self.pos += 1;
+            match c {
+                '\\' => {
+                    if let Some(&escaped) = self.chars.get(self.pos) {
+                        if escaped != '\n' {
+                            self.emit(escaped);
+                            self.pos += 1;
+                        }
+                    }
+                }
+                '[' => in_class = true,
+                ']' => in_class = false,
+                '/' if !in_class => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Copies a char literal like `'a'` or `'\n'`, or just the `'` of a lifetime.
+    fn copy_rust_quote(&mut self) {
+        let next = self.chars.get(self.pos + 1).copied();
+        let is_char = next == Some('\\') || self.chars.get(self.pos + 2) == Some(&'\'');
+        if is_char {
+            self.copy_string("'", true);
+        } else {
+            self.emit('\'');
+            self.pos += 1;
+        }
+    }
+}
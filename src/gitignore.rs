@@ -0,0 +1,155 @@
+use crate::{display_path, warn, Config};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::path::{self, Path};
+use std::sync::Arc;
+
+/// Per-project ignore file read from the root of the processed directory.
+const PROMPTIFYIGNORE: &str = ".promptifyignore";
+
+/// Gitignore-style rules for the directory being walked: the root's
+/// `.promptifyignore` and any extra ignore files, plus `.gitignore` matchers
+/// from the outermost directory down to the current one when enabled.
+#[derive(Clone, Default)]
+pub(crate) struct IgnoreStack {
+    enabled: bool,
+    /// How to warn about ignore files that cannot be loaded.
+    config: Arc<Config>,
+    matchers: Vec<Gitignore>,
+    promptifyignore: Option<Gitignore>,
+    /// Files like `.dockerignore`, each applying on its own.
+    extra: Vec<Gitignore>,
+}
+
+impl IgnoreStack {
+    /// Starts a stack for the directory of `config`, picking up the
+    /// `.gitignore` files of its parent directories up to the enclosing git
+    /// repository, if `enabled`. The `.gitignore` of the directory itself is
+    /// added when the walk descends into it. Relative
+    /// [`Config::ignore_files`] are looked up in the directory, and missing
+    /// ones are skipped.
+    pub(crate) fn new(config: &Config, enabled: bool) -> Self {
+        let root = config.directory.as_path();
+        let mut stack = IgnoreStack {
+            enabled,
+            config: Arc::new(config.clone()),
+            matchers: vec![],
+            promptifyignore: load(root, PROMPTIFYIGNORE, config),
+            extra: config
+                .ignore_files
+                .iter()
+                .filter_map(|file| load_file(&root.join(file), config))
+                .collect(),
+        };
+        if !enabled {
+            return stack;
+        }
+
+        if let Ok(root) = path::absolute(root) {
+            let parents: Vec<&Path> = root.ancestors().skip(1).collect();
+            if let Some(repo) = parents.iter().position(|dir| dir.join(".git").exists()) {
+                for dir in parents[..=repo].iter().rev() {
+                    stack.push(dir);
+                }
+            }
+        }
+        stack
+    }
+
+    /// Returns the stack to use for the entries of `dir`.
+    pub(crate) fn descend(&self, dir: &Path) -> Self {
+        let mut stack = self.clone();
+        stack.push(dir);
+        stack
+    }
+
+    fn push(&mut self, dir: &Path) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(matcher) = load(dir, ".gitignore", &self.config) {
+            self.matchers.push(matcher);
+        }
+    }
+
+    /// A path is ignored when `.promptifyignore`, one of the extra ignore
+    /// files or, if enabled, the `.gitignore` files exclude it. Negations
+    /// only re-include paths excluded by rules from the same kind of file.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.promptifyignore.is_none() && self.extra.is_empty() && !self.enabled {
+            return false;
+        }
+        // git never tracks its own metadata directory
+        if self.enabled && is_dir && path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        let Ok(path) = path::absolute(path) else {
+            return false;
+        };
+
+        if self
+            .promptifyignore
+            .iter()
+            .chain(&self.extra)
+            .any(|matcher| matcher.matched(&path, is_dir).is_ignore())
+        {
+            return true;
+        }
+
+        // The deepest .gitignore with an opinion wins, so negations can re-include files
+        for matcher in self.matchers.iter().rev() {
+            match matcher.matched(&path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+/// Loads the ignore file `name` in `dir`, if there is one.
+fn load(dir: &Path, name: &str, config: &Config) -> Option<Gitignore> {
+    load_file(&dir.join(name), config)
+}
+
+/// Loads an ignore file if it exists. Its rules are relative to the
+/// directory containing it. Problems with it are warned about, showing its
+/// path as the other warnings of `config` do.
+fn load_file(file: &Path, config: &Config) -> Option<Gitignore> {
+    if !file.is_file() {
+        return None;
+    }
+
+    let shown = display_path(config, &config.directory, file);
+    let file = path::absolute(file).ok()?;
+    let mut builder = GitignoreBuilder::new(file.parent()?);
+    for problem in builder.add(&file).map(problems).unwrap_or_default() {
+        warn(
+            config,
+            format_args!("Warning: Invalid rule in {}: {}", shown, problem),
+        );
+    }
+    match builder.build() {
+        Ok(matcher) => Some(matcher),
+        Err(err) => {
+            for problem in problems(err) {
+                warn(
+                    config,
+                    format_args!("Warning: Could not load {}: {}", shown, problem),
+                );
+            }
+            None
+        }
+    }
+}
+
+/// The problems in `err`, each without the path of the ignore file, which the
+/// warnings show already.
+fn problems(err: ignore::Error) -> Vec<ignore::Error> {
+    match err {
+        ignore::Error::Partial(errs) => errs.into_iter().flat_map(problems).collect(),
+        ignore::Error::WithPath { err, .. } => problems(*err),
+        err => vec![err],
+    }
+}
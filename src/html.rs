@@ -0,0 +1,266 @@
+//! Standalone HTML pages for `--format html`.
+//!
+//! The tree becomes a nested list and each file a highlighted `<pre>` block.
+//! Highlighting reuses the lexer of `--strip-comments` for comments and
+//! strings, and marks keywords and numbers on top of it, so languages it
+//! does not know are shown escaped but plain. The grammars of `syntect`
+//! would cover types, functions and nested syntax too, but it is not a
+//! dependency.
+
+use crate::comments::{literal_spans, Literal};
+use crate::{
+    file_label, format_size, shows_tree, strip_shown_prefix, tree_root, xml_escape, Config,
+    FileEntry, Kind, SectionView, ShownPaths, TreeItem,
+};
+use std::io::{self, Write};
+
+/// Styles of the page, kept inline so it stays a single file.
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }
+pre { background: #f6f8fa; padding: 1rem; border-radius: 6px; overflow-x: auto; }
+.tree, .tree ul { list-style: none; padding-left: 1.25rem; font-family: ui-monospace, monospace; }
+.tree { padding-left: 0; }
+.dir { font-weight: bold; }
+.note, .details { color: #59636e; }
+.error { color: #d1242f; }
+.kw { color: #cf222e; }
+.str { color: #0a3069; }
+.num { color: #0550ae; }
+.comment { color: #59636e; font-style: italic; }
+";
+
+pub(crate) fn write_html(
+    out: &mut dyn Write,
+    config: &Config,
+    sections: &[SectionView],
+) -> io::Result<()> {
+    let shown = ShownPaths::new(config, sections);
+    let title = match sections {
+        [section] => tree_root(config, section.directory).display().to_string(),
+        _ => "promptify".to_string(),
+    };
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", xml_escape(&title))?;
+    writeln!(out, "<style>\n{}</style>", STYLE)?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    for section in sections {
+        if sections.len() > 1 {
+            let directory = section.directory.display().to_string();
+            writeln!(
+                out,
+                "<h1>Directory: {}</h1>",
+                xml_escape(&strip_shown_prefix(config, directory))
+            )?;
+        }
+        if shows_tree(config) {
+            write_heading(out, &config.headers.tree)?;
+            let root = tree_root(config, section.directory);
+            writeln!(out, "<ul class=\"tree\">")?;
+            write_tree_item(out, &root.display().to_string(), section.tree)?;
+            writeln!(out, "</ul>")?;
+        }
+        if !config.tree_only {
+            write_heading(out, &config.headers.files)?;
+            for file in section.files.iter() {
+                write_html_file(out, &shown.get(section.directory, &file.path), &file)?;
+            }
+        }
+    }
+    if let Some(prompt) = &config.prompt {
+        write_heading(out, &config.headers.prompt)?;
+        writeln!(out, "<pre class=\"prompt\">{}</pre>", xml_escape(prompt))?;
+    }
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")
+}
+
+/// Writes a section heading from its markdown form, such as `### Files:`.
+fn write_heading(out: &mut dyn Write, header: &str) -> io::Result<()> {
+    let text = header.trim_start_matches('#').trim().trim_end_matches(':');
+    match text {
+        "" => Ok(()),
+        text => writeln!(out, "<h2>{}</h2>", xml_escape(text)),
+    }
+}
+
+/// Writes a tree entry as a list item, its children as a nested list.
+fn write_tree_item(out: &mut dyn Write, name: &str, item: &TreeItem) -> io::Result<()> {
+    write!(out, "<li>")?;
+    match item.kind {
+        Kind::Dir => write!(out, "<span class=\"dir\">{}/</span>", xml_escape(name))?,
+        Kind::File => write!(out, "{}", xml_escape(name))?,
+    }
+    if let Some(size) = item.size {
+        write!(out, " <span class=\"note\">({})</span>", format_size(size))?;
+    }
+    if item.truncated {
+        write!(out, " <span class=\"note\">...</span>")?;
+    }
+    if item.permission_denied {
+        write!(
+            out,
+            " <span class=\"note\">&lt;permission denied&gt;</span>"
+        )?;
+    }
+    if !item.children.is_empty() {
+        writeln!(out, "\n<ul>")?;
+        for child in &item.children {
+            write_tree_item(out, &child.name, child)?;
+        }
+        writeln!(out, "</ul>")?;
+    }
+    writeln!(out, "</li>")
+}
+
+pub(crate) fn write_html_file(
+    out: &mut dyn Write,
+    shown: &str,
+    file: &FileEntry,
+) -> io::Result<()> {
+    writeln!(out, "<section class=\"file\">")?;
+    writeln!(out, "<h3>{}</h3>", xml_escape(&file_label(shown, file)))?;
+    let mut details = vec![];
+    if let Some(metadata) = &file.metadata {
+        details.push(format!("size: {}", format_size(metadata.size)));
+        if let Some(modified) = &metadata.modified {
+            details.push(format!("modified: {}", modified));
+        }
+    }
+    match file.line_count {
+        Some(1) => details.push("1 line".to_string()),
+        Some(count) => details.push(format!("{} lines", count)),
+        None => {}
+    }
+    if !details.is_empty() {
+        writeln!(out, "<p class=\"details\">{}</p>", details.join(", "))?;
+    }
+    match (&file.content, &file.error) {
+        (Some(content), _) => {
+            let language = match file.language.as_str() {
+                "" => String::new(),
+                language => format!(" class=\"language-{}\"", xml_escape(language)),
+            };
+            write!(out, "<pre><code{}>", language)?;
+            write!(out, "{}", highlight(content, &file.language))?;
+            writeln!(out, "</code></pre>")?;
+        }
+        (None, error) => writeln!(
+            out,
+            "<p class=\"error\">Error reading file: {}</p>",
+            xml_escape(error.as_deref().unwrap_or_default())
+        )?,
+    }
+    writeln!(out, "</section>")
+}
+
+/// `content` escaped for HTML, with comments, strings, keywords and numbers
+/// wrapped in `<span>`s for the styles.
+fn highlight(content: &str, language: &str) -> String {
+    let keywords = keywords(language);
+    let spans = match literal_spans(content, language) {
+        Some(spans) => spans,
+        None if keywords.is_empty() => return xml_escape(content),
+        None => vec![],
+    };
+    let chars: Vec<char> = content.chars().collect();
+    let mut highlighted = String::with_capacity(content.len());
+    let mut pos = 0;
+    for (range, literal) in spans
+        .into_iter()
+        .chain([(chars.len()..chars.len(), Literal::String)])
+    {
+        highlight_code(&chars[pos..range.start], keywords, &mut highlighted);
+        if !range.is_empty() {
+            let class = match literal {
+                Literal::Comment => "comment",
+                Literal::String => "str",
+            };
+            let text: String = chars[range.clone()].iter().collect();
+            highlighted.push_str(&span(class, &text));
+        }
+        pos = range.end;
+    }
+    highlighted
+}
+
+/// Highlights the keywords and numbers of code between literals.
+fn highlight_code(chars: &[char], keywords: &[&str], highlighted: &mut String) {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_word(chars[i]) {
+            highlighted.push_str(&xml_escape(&chars[i].to_string()));
+            i += 1;
+            continue;
+        }
+        let end = (i..chars.len())
+            .find(|&j| !is_word(chars[j]))
+            .unwrap_or(chars.len());
+        let word: String = chars[i..end].iter().collect();
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            highlighted.push_str(&span("num", &word));
+        } else if keywords.contains(&word.as_str()) {
+            highlighted.push_str(&span("kw", &word));
+        } else {
+            highlighted.push_str(&xml_escape(&word));
+        }
+        i = end;
+    }
+}
+
+fn span(class: &str, text: &str) -> String {
+    format!("<span class=\"{}\">{}</span>", class, xml_escape(text))
+}
+
+#[rustfmt::skip]
+fn keywords(language: &str) -> &'static [&'static str] {
+    match language {
+        "rust" => &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        "python" => &[
+            "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+            "continue", "def", "del", "elif", "else", "except", "finally", "for", "from",
+            "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass",
+            "raise", "return", "try", "while", "with", "yield",
+        ],
+        "javascript" | "typescript" => &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue", "default",
+            "delete", "do", "else", "export", "extends", "false", "finally", "for", "from",
+            "function", "if", "import", "in", "instanceof", "interface", "let", "new", "null",
+            "of", "return", "static", "super", "switch", "this", "throw", "true", "try", "type",
+            "typeof", "undefined", "var", "void", "while", "yield",
+        ],
+        "go" => &[
+            "break", "case", "chan", "const", "continue", "default", "defer", "else", "false",
+            "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+            "range", "return", "select", "struct", "switch", "true", "type", "var",
+        ],
+        "c" | "cpp" | "java" | "php" => &[
+            "abstract", "auto", "bool", "boolean", "break", "case", "catch", "char", "class",
+            "const", "continue", "default", "do", "double", "echo", "else", "enum", "extends",
+            "extern", "false", "final", "float", "for", "function", "if", "implements", "import",
+            "int", "interface", "long", "namespace", "new", "null", "nullptr", "package",
+            "private", "protected", "public", "return", "short", "signed", "sizeof", "static",
+            "struct", "switch", "template", "this", "throw", "true", "try", "typedef", "union",
+            "unsigned", "using", "virtual", "void", "volatile", "while",
+        ],
+        "ruby" => &[
+            "begin", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "if",
+            "module", "nil", "require", "rescue", "return", "self", "then", "true", "unless",
+            "until", "when", "while", "yield",
+        ],
+        "bash" => &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        _ => &[],
+    }
+}
@@ -0,0 +1,311 @@
+//! Import following for `--follow-imports`.
+//!
+//! Starting from entry files, the files they reference are found by scanning
+//! for Rust `mod` and `use` declarations and JavaScript/TypeScript `import`,
+//! `export ... from` and `require` calls. Only local references are followed:
+//! `crate`, `self` and `super` paths in Rust, and relative specifiers such as
+//! `./util` in JavaScript. The parsing is line based rather than a real
+//! parser, so the odd reference may be missed.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Extensions tried, in order, for a JavaScript/TypeScript specifier without
+/// one, also for `index` files of directories.
+const SCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// The entry files and every file they transitively reference, relative to
+/// `directory` and in the order they were found. Entries are relative to
+/// `directory` too.
+pub fn follow_imports(directory: &Path, entries: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let root = fs::canonicalize(directory)?;
+    let mut seen = HashSet::new();
+    let mut found = vec![];
+    let mut queue = vec![];
+    for entry in entries {
+        let path = directory.join(entry);
+        if !path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a file", path.display()),
+            ));
+        }
+        queue.push(path);
+    }
+    queue.reverse();
+
+    while let Some(path) = queue.pop() {
+        // Only files inside the directory can be rendered
+        let Ok(canonical) = fs::canonicalize(&path) else {
+            continue;
+        };
+        let Ok(relative) = canonical.strip_prefix(&root) else {
+            continue;
+        };
+        let relative = relative.to_path_buf();
+        if !seen.insert(canonical) {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let mut references = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => rust_references(&path, &content),
+            Some(ext) if SCRIPT_EXTENSIONS.contains(&ext) => script_references(&path, &content),
+            _ => vec![],
+        };
+        references.reverse();
+        queue.extend(references);
+        found.push(relative);
+    }
+    Ok(found)
+}
+
+/// The files of the modules declared by `mod name;` and of the `crate`,
+/// `self` and `super` paths named by `use`, including paths into the library
+/// of the same package by its name.
+fn rust_references(path: &Path, content: &str) -> Vec<PathBuf> {
+    let module_dir = rust_module_dir(path);
+    let package = rust_package(path);
+    let mut references = vec![];
+    for statement in rust_statements(content) {
+        if let Some(name) = statement.strip_prefix("mod ") {
+            references.extend(rust_module_file(&module_dir, name.trim()));
+        } else if let Some(tree) = statement.strip_prefix("use ") {
+            for use_path in expand_use_tree(tree) {
+                // Renames only matter to the importing file
+                let use_path = use_path.split(" as ").next().unwrap_or_default();
+                let mut segments = use_path.split("::").map(str::trim);
+                // Items of a crate root live in `lib.rs` or `main.rs`
+                let mut deepest = None;
+                let mut dir = match (segments.next(), &package) {
+                    (Some("crate"), Some((src, _))) => {
+                        deepest = rust_root_file(src, &["lib.rs", "main.rs"]);
+                        src.clone()
+                    }
+                    (Some(first), Some((src, Some(name)))) if first == name => {
+                        deepest = rust_root_file(src, &["lib.rs"]);
+                        src.clone()
+                    }
+                    (Some("self"), _) => module_dir.clone(),
+                    (Some("super"), _) => match module_dir.parent() {
+                        Some(parent) => parent.to_path_buf(),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                // The deepest module file along the path, as the rest may be
+                // items rather than modules
+                for segment in segments {
+                    if segment == "super" {
+                        dir.pop();
+                        continue;
+                    }
+                    match rust_module_file(&dir, segment) {
+                        Some(file) => {
+                            deepest = Some(file);
+                            dir.push(segment);
+                        }
+                        None => break,
+                    }
+                }
+                references.extend(deepest);
+            }
+        }
+    }
+    references
+}
+
+/// The `mod` and `use` statements of Rust source, each joined onto one line
+/// and without visibility or the closing `;`. Inline `mod name { ... }`
+/// blocks are left out.
+fn rust_statements(content: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut pending: Option<String> = None;
+    for line in content.lines() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        let statement = match pending.take() {
+            Some(start) => format!("{} {}", start, line),
+            None => strip_visibility(line).to_string(),
+        };
+        if statement.starts_with("mod ") {
+            if let Some((name, _)) = statement.split_once(';') {
+                if !name.contains('{') {
+                    statements.push(name.to_string());
+                }
+            }
+        } else if statement.starts_with("use ") {
+            match statement.split_once(';') {
+                Some((tree, _)) => statements.push(tree.to_string()),
+                None => pending = Some(statement),
+            }
+        }
+    }
+    statements
+}
+
+/// `line` without a leading `pub`, `pub(crate)` or similar.
+pub(crate) fn strip_visibility(line: &str) -> &str {
+    let Some(rest) = line.strip_prefix("pub") else {
+        return line;
+    };
+    match rest.strip_prefix('(') {
+        Some(rest) => rest
+            .split_once(')')
+            .map_or(line, |(_, rest)| rest.trim_start()),
+        None if rest.starts_with(' ') => rest.trim_start(),
+        None => line,
+    }
+}
+
+/// Expands the braces of a use tree: `a::{b, c::{d, e}}` gives `a::b`,
+/// `a::c::d` and `a::c::e`.
+fn expand_use_tree(tree: &str) -> Vec<String> {
+    let tree = tree.trim();
+    let Some(open) = tree.find('{') else {
+        return vec![tree.to_string()];
+    };
+    let Some(close) = tree.rfind('}') else {
+        return vec![];
+    };
+    let prefix = &tree[..open];
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut start = open + 1;
+    for (i, c) in tree.char_indices().take(close).skip(open + 1) {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&tree[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&tree[start..close]);
+    items
+        .into_iter()
+        .filter(|item| !item.trim().is_empty())
+        .flat_map(expand_use_tree)
+        .map(|item| match item.as_str() {
+            "self" => prefix.trim_end_matches("::").to_string(),
+            _ => format!("{}{}", prefix, item),
+        })
+        .collect()
+}
+
+/// Whether `path` is a file whose submodules live next to it: a crate root
+/// or a `mod.rs`.
+fn rust_is_root_file(path: &Path) -> bool {
+    matches!(
+        path.file_name().and_then(|name| name.to_str()),
+        Some("lib.rs" | "main.rs" | "mod.rs")
+    )
+}
+
+/// The directory holding the submodules of the module in `path`.
+fn rust_module_dir(path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    if rust_is_root_file(path) {
+        return parent;
+    }
+    match path.file_stem() {
+        Some(stem) => parent.join(stem),
+        None => parent,
+    }
+}
+
+/// The file of the module `name` in `dir`: `name.rs` or `name/mod.rs`.
+fn rust_module_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = name.strip_prefix("r#").unwrap_or(name);
+    [
+        dir.join(format!("{}.rs", name)),
+        dir.join(name).join("mod.rs"),
+    ]
+    .into_iter()
+    .find(|file| file.is_file())
+}
+
+/// The `src` directory of the package `path` belongs to, the one next to the
+/// closest `Cargo.toml` above it, and the package's name as used in paths.
+fn rust_package(path: &Path) -> Option<(PathBuf, Option<String>)> {
+    let dir = path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").is_file())?;
+    let name = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+        .and_then(|manifest| {
+            let name = manifest.get("package")?.get("name")?.as_str()?;
+            Some(name.replace('-', "_"))
+        });
+    Some((dir.join("src"), name))
+}
+
+/// The first of the crate root `names` that exists in `src`.
+fn rust_root_file(src: &Path, names: &[&str]) -> Option<PathBuf> {
+    names
+        .iter()
+        .map(|name| src.join(name))
+        .find(|file| file.is_file())
+}
+
+/// The local files referenced by `import`, `export ... from`, `import()` and
+/// `require()` in JavaScript or TypeScript source.
+fn script_references(path: &Path, content: &str) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut references = vec![];
+    for keyword in ["from", "import", "require("] {
+        let mut rest = content;
+        while let Some(start) = rest.find(keyword) {
+            let before = rest[..start].chars().next_back();
+            rest = &rest[start + keyword.len()..];
+            // Skip words that merely end in the keyword, such as `reimport`
+            if before.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '$') {
+                continue;
+            }
+            let Some(specifier) = quoted_specifier(rest) else {
+                continue;
+            };
+            if specifier.starts_with("./") || specifier.starts_with("../") {
+                references.extend(resolve_script(&dir.join(specifier)));
+            }
+        }
+    }
+    references
+}
+
+/// The string literal right after a keyword, allowing for whitespace and the
+/// opening parenthesis of a call.
+fn quoted_specifier(text: &str) -> Option<&str> {
+    let text = text.trim_start();
+    let text = text.strip_prefix('(').unwrap_or(text).trim_start();
+    let quote = text
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '"' | '\'' | '`'))?;
+    let text = &text[1..];
+    text.find(quote).map(|end| &text[..end])
+}
+
+/// The file a relative specifier points to, trying the usual extensions and
+/// `index` files.
+fn resolve_script(base: &Path) -> Option<PathBuf> {
+    if base.is_file() {
+        return Some(base.to_path_buf());
+    }
+    let mut candidates = vec![];
+    for ext in SCRIPT_EXTENSIONS {
+        let mut file = base.as_os_str().to_owned();
+        file.push(format!(".{}", ext));
+        candidates.push(PathBuf::from(file));
+    }
+    for ext in SCRIPT_EXTENSIONS {
+        candidates.push(base.join(format!("index.{}", ext)));
+    }
+    candidates.into_iter().find(|file| file.is_file())
+}
@@ -0,0 +1,3239 @@
+//! Formats plaintext directories into LLM friendly text.
+//!
+//! The traversal and rendering behind the `promptify` command line tool:
+//! describe a run with a [`Config`], then either call [`render`] (or
+//! [`render_to_writer`] to stream the output) or combine [`build_tree`],
+//! [`collect_files`] and [`render_document`] yourself.
+
+pub mod archive;
+mod cache;
+mod comments;
+mod gitignore;
+mod html;
+pub mod imports;
+pub mod manifest;
+mod minify;
+mod notebook;
+mod redact;
+mod summary;
+mod table;
+pub mod tokens;
+pub mod watch;
+
+use base64::prelude::*;
+use gitignore::IgnoreStack;
+use glob::Pattern;
+use mime_guess::MimeGuess;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Output formats supported by [`render_document`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Markdown,
+    Json,
+    /// Files separated by `===== path =====` lines, without any markup.
+    Plain,
+    /// XML-tagged `<documents>`, as recommended for Claude prompts.
+    Xml,
+    /// A standalone HTML page with the files syntax-highlighted, for browsers.
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            "plain" => Ok(Format::Plain),
+            "xml" => Ok(Format::Xml),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
+
+/// What to do with files that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Replace invalid sequences with the U+FFFD replacement character.
+    #[default]
+    Lossy,
+    /// Leave the file out of the listing.
+    Skip,
+    /// Report the file as unreadable.
+    Strict,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(Encoding::Lossy),
+            "skip" => Ok(Encoding::Skip),
+            "strict" => Ok(Encoding::Strict),
+            _ => Err(format!("unknown encoding handling '{}'", s)),
+        }
+    }
+}
+
+/// The order of the files in the listing. The tree keeps its own order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// As in the tree: directories first, then by name.
+    #[default]
+    Tree,
+    /// By path, not grouping directories first.
+    Alpha,
+    /// Largest first.
+    Size,
+    /// Most recently modified first.
+    Mtime,
+    /// By extension, then by path.
+    Type,
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(Order::Tree),
+            "alpha" => Ok(Order::Alpha),
+            "size" => Ok(Order::Size),
+            "mtime" => Ok(Order::Mtime),
+            "type" => Ok(Order::Type),
+            _ => Err(format!("unknown order '{}'", s)),
+        }
+    }
+}
+
+/// Lines `start` to `end` of a file, counting from 1 and both included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl FromStr for LineRange {
+    type Err = String;
+
+    /// Parses `40-80`, or `42` for a single line.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let parse = |n: &str| n.trim().parse::<usize>().ok().filter(|&n| n > 0);
+        match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start <= end => Ok(LineRange { start, end }),
+            _ => Err(format!("invalid line range '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{}", self.start),
+            false => write!(f, "{}-{}", self.start, self.end),
+        }
+    }
+}
+
+/// A shell command the contents of matching files are piped through, given
+/// as `PATTERN=COMMAND` such as `*.sql=sqlformat -`.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    /// Matched against the file name, or the path relative to the directory
+    /// when it holds a `/`, as exclude patterns are.
+    pub pattern: Pattern,
+    pub command: String,
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid transform '{}', expected PATTERN=COMMAND", s);
+        let (pattern, command) = s.split_once('=').ok_or_else(invalid)?;
+        let (pattern, command) = (pattern.trim(), command.trim());
+        if pattern.is_empty() || command.is_empty() {
+            return Err(invalid());
+        }
+        let pattern = Pattern::new(pattern)
+            .map_err(|err| format!("invalid transform pattern '{}': {}", pattern, err))?;
+        Ok(Transform {
+            pattern,
+            command: command.to_string(),
+        })
+    }
+}
+
+/// Splits a line of a file list such as `src/main.rs:40-80,100-120` into the
+/// path and its line ranges. A path without a `:` suffix of ranges is taken
+/// whole, with no ranges.
+pub fn parse_file_spec(spec: &str) -> Result<(PathBuf, Vec<LineRange>), String> {
+    let Some((path, ranges)) = spec.rsplit_once(':') else {
+        return Ok((PathBuf::from(spec), vec![]));
+    };
+    let looks_like_ranges = ranges
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '-' | ',' | ' '))
+        && ranges.contains(|c: char| c.is_ascii_digit());
+    if path.is_empty() || !looks_like_ranges {
+        return Ok((PathBuf::from(spec), vec![]));
+    }
+    let ranges = ranges
+        .split(',')
+        .filter(|range| !range.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("{} in {}", e, spec))?;
+    Ok((PathBuf::from(path), ranges))
+}
+
+/// Which messages about the walk are printed to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// No warnings about skipped or unreadable files.
+    Quiet,
+    #[default]
+    Normal,
+    /// Also explain why entries are left out of the listing.
+    Verbose,
+}
+
+/// Which files to select and how to render them.
+///
+/// Deserializes from a table with the same field names, where every field is
+/// optional and the patterns are lists of globs.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory to process, or a single file to render on its own.
+    pub directory: PathBuf,
+    /// Paths matching any of these patterns are skipped.
+    #[serde(deserialize_with = "deserialize_patterns")]
+    pub exclude_patterns: Vec<Pattern>,
+    /// When non-empty, only files matching one of these patterns are listed.
+    /// Excludes take precedence.
+    #[serde(deserialize_with = "deserialize_patterns")]
+    pub include_patterns: Vec<Pattern>,
+    /// Also skip paths matched by `.gitignore` rules.
+    pub gitignore: bool,
+    /// More gitignore-style files, such as `.dockerignore`, relative to
+    /// `directory`. Missing ones are skipped.
+    pub ignore_files: Vec<PathBuf>,
+    pub encoding: Encoding,
+    /// Directory caching file contents between runs, so only files whose
+    /// size or modification time changed are read again.
+    pub cache_dir: Option<PathBuf>,
+    /// Files larger than this many bytes are shown with a placeholder.
+    pub max_file_size: Option<u64>,
+    /// Fail before reading anything when more files than this would be
+    /// included, such as when pointed at `/` by mistake.
+    pub max_files: Option<usize>,
+    /// Read at most this many files at once, one per CPU unless set.
+    pub jobs: Option<usize>,
+    pub format: Format,
+    /// Whether documents start with the file tree. By default every format
+    /// but plain text shows it.
+    pub show_tree: Option<bool>,
+    /// Render just the trees and the prompt, without listing or reading any
+    /// file.
+    pub tree_only: bool,
+    /// Section headings of markdown documents.
+    pub headers: Headers,
+    /// Appended to the end of the output.
+    pub prompt: Option<String>,
+    /// Prefix each content line with its line number, in files with at least
+    /// this many lines.
+    pub line_numbers: Option<usize>,
+    /// Show only the first and last this many lines of longer files.
+    pub truncate_lines: Option<usize>,
+    /// Directories more than this many levels below `directory` (which is at
+    /// depth 0) are shown in the tree but not read.
+    pub max_depth: Option<usize>,
+    /// Show the size of each file in the tree, and the total size of the
+    /// files listed below each directory.
+    pub tree_sizes: bool,
+    /// Fence languages by lower case file extension, taking precedence over
+    /// the built-in ones.
+    pub languages: HashMap<String, String>,
+    /// Lower case extensions of binary files to include base64-encoded,
+    /// e.g. `png`. They are limited to `max_file_size`, or 1MB by default.
+    pub include_binary: Vec<String>,
+    /// Only list files whose detected language is one of these, e.g. `rust`.
+    pub only_languages: Vec<String>,
+    /// Leave out files whose detected language is one of these.
+    pub skip_languages: Vec<String>,
+    /// Include entries whose name starts with `.`, which are skipped by default.
+    pub hidden: bool,
+    /// List the contents of lockfiles such as `Cargo.lock`, which are only
+    /// shown in the tree by default.
+    pub include_lockfiles: bool,
+    /// Keep directories without any listed entry in the tree, which are pruned
+    /// by default.
+    pub keep_empty_dirs: bool,
+    /// Only list files modified after this time.
+    #[serde(deserialize_with = "deserialize_time")]
+    pub modified_since: Option<SystemTime>,
+    /// Only list files of at least this many bytes.
+    pub min_size: Option<u64>,
+    /// Only list files of at most this many bytes, unlike `max_file_size`
+    /// which keeps larger files with a placeholder.
+    pub max_size: Option<u64>,
+    /// Only walk these paths relative to `directory`, such as `packages/foo`,
+    /// and the directories leading to them.
+    pub scopes: Vec<PathBuf>,
+    /// Show the paths in the tree and the listing relative to this directory
+    /// instead of `directory`, such as the repository root.
+    pub relative_to: Option<PathBuf>,
+    /// Record what the walk leaves out in [`Section::excluded`], with the
+    /// number and size of the files below excluded directories.
+    pub show_excluded_summary: bool,
+    /// Remove this literal text from the start of the paths shown in the
+    /// tree, the headings and the file labels, such as a long absolute one.
+    pub strip_prefix: Option<String>,
+    /// Label the listed files by their names alone, numbered where several
+    /// share one. The tree is unaffected.
+    pub flatten: bool,
+    /// Walk into symlinked files and directories instead of skipping them.
+    pub follow_symlinks: bool,
+    /// Mask values that look like secrets, such as `.env` values and API keys.
+    pub redact: bool,
+    /// Remove comments from files in languages with a known comment syntax.
+    pub strip_comments: bool,
+    /// Show only the declarations of Rust and Python files, without bodies.
+    pub summary_only: bool,
+    /// Strip trailing whitespace, leading and trailing blank lines, and
+    /// collapse runs of three or more blank lines into one.
+    pub trim: bool,
+    /// Include the outputs of code cells when converting notebooks.
+    pub notebook_outputs: bool,
+    /// Data rows kept from CSV and TSV files, 10 unless set. Longer tables are
+    /// cut down to their header and these rows, with a note of the row count.
+    pub csv_rows: Option<usize>,
+    /// Show files whose contents match an earlier file as a reference to it.
+    pub dedupe: bool,
+    /// Compact JSON and YAML files, dropping insignificant whitespace.
+    pub minify: bool,
+    /// Commands the contents of matching files are piped through before any
+    /// other content option applies, the first matching one for each file.
+    #[serde(deserialize_with = "deserialize_transforms")]
+    pub transforms: Vec<Transform>,
+    /// Leave out files that look minified, with an average line length of
+    /// hundreds of characters.
+    pub skip_minified: bool,
+    /// Leave out files that could not be read rather than showing them with a
+    /// placeholder. The error is reported on stderr either way.
+    pub no_content_on_error: bool,
+    /// Show each file's size and modification date above its contents.
+    pub metadata: bool,
+    /// Show how many lines each file has above its contents.
+    pub line_counts: bool,
+    /// Color the tree with ANSI codes, for reading it in a terminal.
+    pub color: bool,
+    /// List the files as links to their contents before them, in markdown.
+    pub toc: bool,
+    /// Which warnings and explanations are printed to stderr.
+    pub verbosity: Verbosity,
+    /// The order of the files in the listing.
+    pub order: Order,
+    /// Render exactly these paths, relative to `directory`, instead of
+    /// walking it. The exclude and include patterns still apply.
+    pub files: Option<Vec<PathBuf>>,
+    /// Only render these lines of some of the listed `files`, keyed by their
+    /// path as listed.
+    pub line_ranges: HashMap<PathBuf, Vec<LineRange>>,
+}
+
+/// Headings of the markdown sections. An empty heading is left out.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Headers {
+    pub tree: String,
+    pub files: String,
+    pub prompt: String,
+}
+
+impl Default for Headers {
+    fn default() -> Self {
+        Headers {
+            tree: "### File Tree:".to_string(),
+            files: "### Files:".to_string(),
+            prompt: "### Prompt:".to_string(),
+        }
+    }
+}
+
+impl Headers {
+    /// Leaves out every heading.
+    pub fn none() -> Self {
+        Headers {
+            tree: String::new(),
+            files: String::new(),
+            prompt: String::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Config {
+            directory: directory.into(),
+            ..Default::default()
+        }
+    }
+}
+
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Pattern>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .flat_map(|pattern| expand_braces(pattern))
+        .map(|pattern| Pattern::new(&pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn deserialize_transforms<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Transform>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|transform| transform.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Splits a comma-separated list of glob patterns, leaving the commas inside
+/// braces alone, and expands the braces of each pattern.
+pub fn split_patterns(list: &str) -> Vec<String> {
+    let mut patterns = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                patterns.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    patterns.push(&list[start..]);
+    patterns
+        .into_iter()
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .flat_map(expand_braces)
+        .collect()
+}
+
+/// Expands shell-style braces, which [`Pattern`] does not support:
+/// `*.{png,jpg}` becomes `*.png` and `*.jpg`. Braces may nest, and braces
+/// without a comma are left as they are.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some((open, close, commas)) = find_braces(pattern) else {
+        return vec![pattern.to_string()];
+    };
+    let mut bounds = vec![open];
+    bounds.extend(commas);
+    bounds.push(close);
+    bounds
+        .windows(2)
+        .flat_map(|bound| {
+            expand_braces(&format!(
+                "{}{}{}",
+                &pattern[..open],
+                &pattern[bound[0] + 1..bound[1]],
+                &pattern[close + 1..]
+            ))
+        })
+        .collect()
+}
+
+/// The first pair of braces with alternatives in `pattern`, as the offsets of
+/// the braces and of the commas between the alternatives.
+fn find_braces(pattern: &str) -> Option<(usize, usize, Vec<usize>)> {
+    let bytes = pattern.as_bytes();
+    for open in (0..bytes.len()).filter(|&i| bytes[i] == b'{') {
+        let mut depth = 0;
+        let mut commas = vec![];
+        for (i, &byte) in bytes.iter().enumerate().skip(open) {
+            match byte {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if !commas.is_empty() {
+                            return Some((open, i, commas));
+                        }
+                        break;
+                    }
+                }
+                b',' if depth == 1 => commas.push(i),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TreeItem {
+    pub name: String,
+    pub kind: Kind,
+    pub children: Vec<TreeItem>,
+    /// Set on directories whose contents were not read because of the depth limit.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Set on directories whose contents could not be listed for lack of
+    /// permission.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub permission_denied: bool,
+    /// Size in bytes of a file, or of the files listed below a directory.
+    /// Only collected when [`Config::tree_sizes`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Whether a [`TreeItem`] is a file or a directory, which an empty directory
+/// could not tell otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    File,
+    Dir,
+}
+
+impl TreeItem {
+    fn new(name: &str, kind: Kind) -> Self {
+        TreeItem {
+            name: name.to_string(),
+            kind,
+            children: vec![],
+            truncated: false,
+            permission_denied: false,
+            size: None,
+        }
+    }
+
+    fn add_child(&mut self, child: TreeItem) {
+        self.children.push(child);
+    }
+}
+
+/// A plaintext file selected for output.
+#[derive(Clone, Debug, Serialize)]
+pub struct FileEntry {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub language: String,
+    /// Set when `content` is a base64-encoded binary file, included with
+    /// [`Config::include_binary`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Set instead of `content` when the file could not be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Only collected when [`Config::metadata`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<FileMetadata>,
+    /// Lines of the file as read, before any content option applies. Only
+    /// counted when [`Config::line_counts`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_count: Option<usize>,
+    /// The lines of the file in `content`, when only some of them are, from
+    /// [`Config::line_ranges`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<LineRange>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FileMetadata {
+    /// Size in bytes.
+    pub size: u64,
+    /// Last modification date as `YYYY-MM-DD` (UTC).
+    pub modified: Option<String>,
+}
+
+/// One processed directory of a document covering several of them.
+#[derive(Clone, Debug, Serialize)]
+pub struct Section {
+    #[serde(serialize_with = "serialize_path")]
+    pub directory: PathBuf,
+    pub tree: TreeItem,
+    pub files: Vec<FileEntry>,
+    /// Exclude patterns that matched nothing while walking the directory,
+    /// such as misspelt ones.
+    #[serde(skip)]
+    pub unmatched_excludes: Vec<String>,
+    /// The entries the walk left out. Only recorded when
+    /// [`Config::show_excluded_summary`] is set.
+    #[serde(skip)]
+    pub excluded: Vec<Excluded>,
+}
+
+/// A file or directory left out of the listing while walking a directory.
+#[derive(Clone, Debug)]
+pub struct Excluded {
+    pub path: PathBuf,
+    pub reason: ExclusionReason,
+    /// The files it holds: one for a file, every file below a directory.
+    pub files: usize,
+    /// Total size in bytes of those files.
+    pub bytes: u64,
+}
+
+/// What left an entry out of the listing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExclusionReason {
+    /// An exclude pattern.
+    Pattern,
+    /// A `.gitignore`, `.promptifyignore` or other ignore file.
+    Ignored,
+    /// A name starting with a dot.
+    Hidden,
+    /// Contents that are not plaintext.
+    Binary,
+    /// The size or modification time filters.
+    Size,
+    /// A lockfile, kept in the tree only.
+    Lockfile,
+    /// The include patterns, language filters, scopes or depth limit.
+    Filtered,
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExclusionReason::Pattern => "exclude patterns",
+            ExclusionReason::Ignored => "ignore files",
+            ExclusionReason::Hidden => "hidden",
+            ExclusionReason::Binary => "binary",
+            ExclusionReason::Size => "size or date filters",
+            ExclusionReason::Lockfile => "lockfiles",
+            ExclusionReason::Filtered => "other filters",
+        })
+    }
+}
+
+impl Section {
+    fn view(&self) -> SectionView<'_> {
+        SectionView {
+            directory: &self.directory,
+            tree: &self.tree,
+            files: Files::Read(&self.files),
+        }
+    }
+}
+
+/// A borrowed [`Section`], so single documents render without copying.
+#[derive(Clone, Copy)]
+struct SectionView<'a> {
+    directory: &'a Path,
+    tree: &'a TreeItem,
+    files: Files<'a>,
+}
+
+/// The files of a [`SectionView`]: either read already, or read a batch at
+/// a time while they are written, for [`render_to_writer`].
+#[derive(Clone, Copy)]
+enum Files<'a> {
+    Read(&'a [FileEntry]),
+    Streamed {
+        config: &'a Config,
+        paths: &'a [PathBuf],
+    },
+}
+
+/// How many files are read at a time, in parallel, when streaming.
+const STREAM_BATCH: usize = 64;
+
+impl<'a> Files<'a> {
+    /// The paths of the files, without reading any.
+    fn paths(self) -> Vec<&'a Path> {
+        match self {
+            Files::Read(files) => files.iter().map(|file| file.path.as_path()).collect(),
+            Files::Streamed { paths, .. } => paths.iter().map(PathBuf::as_path).collect(),
+        }
+    }
+
+    fn iter(self) -> Box<dyn Iterator<Item = Cow<'a, FileEntry>> + 'a> {
+        match self {
+            Files::Read(files) => Box::new(files.iter().map(Cow::Borrowed)),
+            Files::Streamed { config, paths } => Box::new(
+                paths
+                    .chunks(STREAM_BATCH)
+                    .flat_map(move |batch| read_files(config, batch.to_vec(), |_| {}))
+                    .map(Cow::Owned),
+            ),
+        }
+    }
+}
+
+impl Serialize for Files<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// The document emitted by [`Format::Json`] for a single directory.
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree: Option<&'a TreeItem>,
+    files: JsonFiles<'a>,
+    prompt: Option<&'a str>,
+}
+
+/// The document emitted by [`Format::Json`] for several directories.
+#[derive(Serialize)]
+struct JsonSections<'a> {
+    sections: Vec<JsonSection<'a>>,
+    prompt: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonSection<'a> {
+    /// As in the `## Directory:` headings of markdown.
+    directory: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree: Option<&'a TreeItem>,
+    files: JsonFiles<'a>,
+}
+
+/// The files of a JSON document, their `path` fields as [`display_path`]
+/// shows them.
+struct JsonFiles<'a> {
+    config: &'a Config,
+    directory: &'a Path,
+    files: Files<'a>,
+}
+
+impl Serialize for JsonFiles<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !rewrites_paths(self.config) {
+            return self.files.serialize(serializer);
+        }
+        serializer.collect_seq(self.files.iter().map(|file| {
+            let mut file = file.into_owned();
+            file.path = PathBuf::from(display_path(self.config, self.directory, &file.path));
+            file
+        }))
+    }
+}
+
+fn serialize_path<S: Serializer>(
+    path: &impl AsRef<Path>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.as_ref().to_string_lossy())
+}
+
+/// Walks `config.directory` and renders it in the configured format.
+pub fn render(config: &Config) -> io::Result<String> {
+    let tree = build_tree(config)?;
+    let files = collect_files(config)?;
+    Ok(render_document(config, &tree, &files))
+}
+
+/// Walks `config.directory` like [`render`], but writes the document to
+/// `writer` as it goes instead of returning it, holding only a batch of
+/// files in memory at a time. With [`Config::toc`] or [`Config::dedupe`] all
+/// files are needed up front, so they are read before anything is written.
+pub fn render_to_writer(config: &Config, mut writer: impl Write) -> io::Result<()> {
+    let tree = build_tree(config)?;
+    let paths = select_files(config)?;
+    let read;
+    let files = if config.toc || config.dedupe {
+        read = read_files(config, paths, |_| {});
+        Files::Read(&read)
+    } else {
+        Files::Streamed {
+            config,
+            paths: &paths,
+        }
+    };
+    let view = SectionView {
+        directory: &config.directory,
+        tree: &tree,
+        files,
+    };
+    write_views(&mut writer, config, &[view])?;
+    writer.flush()
+}
+
+/// Walks `config.directory` into a section of a multi-directory document.
+pub fn build_section(config: &Config) -> io::Result<Section> {
+    let (tree, unmatched_excludes) = build_tree_checked(config)?;
+    let (paths, excluded) = select_files_with_excluded(config)?;
+    Ok(Section {
+        directory: config.directory.clone(),
+        tree,
+        files: read_files(config, paths, |_| {}),
+        unmatched_excludes,
+        excluded,
+    })
+}
+
+/// A section holding text that is not read from a file, such as piped
+/// input, as its one file labelled `name`. The content options of `config`
+/// apply to it as to any file, and the tree is empty.
+pub fn text_section(config: &Config, name: &str, language: &str, content: String) -> Section {
+    let path = PathBuf::from(name);
+    let line_count = config.line_counts.then(|| content.lines().count());
+    let content = transform_content(content, &path, language, config, false);
+    Section {
+        directory: PathBuf::new(),
+        tree: TreeItem::new(name, Kind::File),
+        files: vec![FileEntry {
+            path,
+            language: language.to_string(),
+            mime: None,
+            content: Some(content),
+            error: None,
+            metadata: None,
+            line_count,
+            lines: vec![],
+        }],
+        unmatched_excludes: vec![],
+        excluded: vec![],
+    }
+}
+
+/// Builds the tree of everything under `config.directory` that is not excluded.
+pub fn build_tree(config: &Config) -> io::Result<TreeItem> {
+    Ok(build_tree_checked(config)?.0)
+}
+
+/// Builds the tree like [`build_tree`], along with the exclude patterns that
+/// matched nothing on the way. Listed `config.files` are not walked, so they
+/// report none.
+pub fn build_tree_checked(config: &Config) -> io::Result<(TreeItem, Vec<String>)> {
+    if let Some(paths) = &config.files {
+        let ignores = IgnoreStack::new(config, false);
+        let listed = paths.iter().filter(|path| {
+            let path = config.directory.join(path);
+            path.is_file() && is_listed(&path, config, &ignores)
+        });
+        return Ok((tree_from_paths(config, listed), vec![]));
+    }
+    let ignores = IgnoreStack::new(config, config.gitignore);
+    let matched = vec![Cell::new(false); config.exclude_patterns.len()];
+    let tree = build_tree_item(&config.directory, config, &ignores, &matched)?;
+    let unmatched = config
+        .exclude_patterns
+        .iter()
+        .zip(&matched)
+        .filter(|(_, matched)| !matched.get())
+        .map(|(pattern, _)| pattern.as_str().to_string())
+        .collect();
+    Ok((tree, unmatched))
+}
+
+/// Reads every selected plaintext file under `config.directory`.
+///
+/// Files are read in parallel, but returned in a deterministic order: the
+/// order of the tree when walking, or as listed in `config.files`.
+pub fn collect_files(config: &Config) -> io::Result<Vec<FileEntry>> {
+    let paths = select_files(config)?;
+    Ok(read_files(config, paths, |_| {}))
+}
+
+/// Reads the given files, as selected by [`select_files`], in parallel and in
+/// order. `on_read` is called from the reading threads after each file.
+pub fn read_files(
+    config: &Config,
+    paths: Vec<PathBuf>,
+    on_read: impl Fn(&Path) + Sync,
+) -> Vec<FileEntry> {
+    let read = || -> Vec<FileEntry> {
+        paths
+            .into_par_iter()
+            .filter_map(|path| {
+                let entry = read_entry(path.clone(), config);
+                on_read(&path);
+                entry
+            })
+            .collect()
+    };
+    // A pool of its own bounds the open files, the global one has a thread per CPU
+    let pool = config
+        .jobs
+        .and_then(|jobs| ThreadPoolBuilder::new().num_threads(jobs).build().ok());
+    let mut files = match pool {
+        Some(pool) => pool.install(read),
+        None => read(),
+    };
+    if config.dedupe {
+        dedupe(config, &mut files);
+    }
+    files
+}
+
+/// Replaces the contents of files identical to an earlier one with a
+/// reference to it.
+fn dedupe(config: &Config, files: &mut [FileEntry]) {
+    let directory = display_base(config, &config.directory);
+    let mut first_by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    for i in 0..files.len() {
+        let Some(content) = files[i]
+            .content
+            .as_ref()
+            .filter(|content| !content.is_empty())
+        else {
+            continue;
+        };
+        let firsts = first_by_hash
+            .entry(xxh3_64(content.as_bytes()))
+            .or_default();
+        // Also compare the contents, in case of a hash collision
+        match firsts
+            .iter()
+            .find(|&&first| files[first].content.as_ref() == Some(content))
+        {
+            Some(&first) => {
+                let original = shown_path(config, directory, &files[first].path);
+                files[i].content = Some(format!("// identical to {}", original));
+            }
+            None => firsts.push(i),
+        }
+    }
+}
+
+/// Paths of every plaintext file that will be listed, in output order,
+/// without reading them.
+pub fn select_files(config: &Config) -> io::Result<Vec<PathBuf>> {
+    Ok(select_files_with_excluded(config)?.0)
+}
+
+/// Selects the files like [`select_files`], along with the entries left out
+/// on the way when [`Config::show_excluded_summary`] is set.
+pub fn select_files_with_excluded(config: &Config) -> io::Result<(Vec<PathBuf>, Vec<Excluded>)> {
+    let walk = walk_files(config)?;
+    Ok((walk.files, walk.excluded))
+}
+
+/// What selecting the files of a config found on the way.
+#[derive(Default)]
+pub(crate) struct Walk {
+    pub files: Vec<PathBuf>,
+    pub excluded: Vec<Excluded>,
+    /// The directories walked into, the root included.
+    pub dirs: Vec<PathBuf>,
+}
+
+pub(crate) fn walk_files(config: &Config) -> io::Result<Walk> {
+    let mut walk = Walk::default();
+    if config.tree_only {
+        return Ok(walk);
+    }
+    if let Some(paths) = &config.files {
+        walk.files = list_files(paths, config);
+    } else if config.directory.is_file() {
+        // A single file given as the directory is listed as is
+        select_file(config.directory.clone(), config, &mut walk.files);
+    } else {
+        let ignores = IgnoreStack::new(config, config.gitignore);
+        list_dir(&config.directory, config, &ignores, &mut walk)?;
+    }
+    check_file_count(config, walk.files.len())?;
+    sort_files(&mut walk.files, config.order);
+    Ok(walk)
+}
+
+/// Fails once more than `config.max_files` files are selected.
+fn check_file_count(config: &Config, count: usize) -> io::Result<()> {
+    match config.max_files {
+        Some(max_files) if count > max_files => Err(io::Error::other(format!(
+            "More than {} files would be included. Raise the limit with --max-files, \
+             or narrow the selection with --exclude, --include or --scope",
+            max_files
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Puts the files in `order`, keeping the given order among equal files.
+fn sort_files(files: &mut [PathBuf], order: Order) {
+    let metadata = |path: &PathBuf| fs::metadata(path).ok();
+    match order {
+        Order::Tree => {}
+        Order::Alpha => files.sort(),
+        Order::Size => {
+            files.sort_by_cached_key(|path| Reverse(metadata(path).map_or(0, |m| m.len())))
+        }
+        Order::Mtime => {
+            files.sort_by_cached_key(|path| Reverse(metadata(path).and_then(|m| m.modified().ok())))
+        }
+        Order::Type => files.sort_by(|a, b| {
+            let extension = |path: &PathBuf| path.extension().map(|ext| ext.to_ascii_lowercase());
+            extension(a).cmp(&extension(b)).then_with(|| a.cmp(b))
+        }),
+    }
+}
+
+/// Drops files from the end of the listing once their rendered contents
+/// would exceed `max_bytes` in total, and returns how many were dropped. The
+/// trees are left complete.
+pub fn apply_size_limit(config: &Config, max_bytes: u64, sections: &mut [Section]) -> usize {
+    let max_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+    keep_within(sections, max_bytes, |file| render_file(config, file).len())
+}
+
+/// Keeps the files of `sections`, in order, while their total `cost` fits in
+/// `budget`, drops the rest and returns how many were dropped.
+pub(crate) fn keep_within(
+    sections: &mut [Section],
+    budget: usize,
+    cost: impl Fn(&FileEntry) -> usize,
+) -> usize {
+    let mut total = 0;
+    let mut omitted = 0;
+    for section in sections.iter_mut() {
+        let mut kept = 0;
+        for file in &section.files {
+            let file_cost = cost(file);
+            if omitted > 0 || total + file_cost > budget {
+                break;
+            }
+            total += file_cost;
+            kept += 1;
+        }
+        omitted += section.files.len() - kept;
+        section.files.truncate(kept);
+    }
+    omitted
+}
+
+/// Renders a tree and its files in the configured format.
+pub fn render_document(config: &Config, tree: &TreeItem, files: &[FileEntry]) -> String {
+    render_views(config, &[single_view(config, tree, files)])
+}
+
+/// Renders several directories into one document, each delimited by a
+/// heading, followed by the prompt. A single section renders exactly like
+/// [`render_document`].
+pub fn render_sections(config: &Config, sections: &[Section]) -> String {
+    let views: Vec<SectionView> = sections.iter().map(Section::view).collect();
+    render_views(config, &views)
+}
+
+/// Renders the parts of a split document, as made by
+/// [`tokens::split_by_tokens`], each headed by its number. Only the first
+/// part shows the trees and only the last one the prompt.
+pub fn render_parts(config: &Config, parts: &[Vec<Section>]) -> Vec<String> {
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let part_config = Config {
+                show_tree: if i == 0 {
+                    config.show_tree
+                } else {
+                    Some(false)
+                },
+                prompt: config.prompt.clone().filter(|_| i + 1 == parts.len()),
+                ..config.clone()
+            };
+            part_header(config.format, i + 1, parts.len()) + &render_sections(&part_config, part)
+        })
+        .collect()
+}
+
+pub(crate) fn part_header(format: Format, part: usize, parts: usize) -> String {
+    match format {
+        Format::Xml | Format::Html => format!("<!-- Part {} of {} -->\n", part, parts),
+        _ => format!("Part {} of {}\n\n", part, parts),
+    }
+}
+
+/// Fills the `{{tree}}`, `{{files}}` and `{{prompt}}` placeholders of
+/// `template` with the rendered trees, files and the prompt.
+pub fn render_template(
+    template: &str,
+    config: &Config,
+    sections: &[Section],
+) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            return Err("unclosed '{{'".to_string());
+        };
+        match rest[start + 2..start + end].trim() {
+            "tree" => {
+                let trees: Vec<String> = sections
+                    .iter()
+                    .map(|section| {
+                        render_to_string(|out| {
+                            let root = tree_root(config, &section.directory);
+                            print_tree(out, &root, &section.tree, config.color)
+                        })
+                    })
+                    .collect();
+                rendered.push_str(&trees.join("\n"));
+            }
+            "files" => {
+                let views: Vec<SectionView> = sections.iter().map(Section::view).collect();
+                let shown = ShownPaths::new(config, &views);
+                for section in sections {
+                    for file in &section.files {
+                        let path = shown.get(&section.directory, &file.path);
+                        rendered.push_str(&render_file_as(config, &path, file));
+                    }
+                }
+            }
+            "prompt" => rendered.push_str(config.prompt.as_deref().unwrap_or_default()),
+            name => {
+                return Err(format!(
+                    "unknown placeholder '{{{{{}}}}}', expected tree, files or prompt",
+                    name
+                ))
+            }
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+pub fn render_markdown(config: &Config, tree: &TreeItem, files: &[FileEntry]) -> String {
+    render_to_string(|out| write_markdown(out, config, &[single_view(config, tree, files)]))
+}
+
+pub fn render_json(config: &Config, tree: &TreeItem, files: &[FileEntry]) -> String {
+    render_to_string(|out| write_json(out, config, &[single_view(config, tree, files)]))
+}
+
+fn single_view<'a>(
+    config: &'a Config,
+    tree: &'a TreeItem,
+    files: &'a [FileEntry],
+) -> SectionView<'a> {
+    SectionView {
+        directory: &config.directory,
+        tree,
+        files: Files::Read(files),
+    }
+}
+
+fn render_views(config: &Config, sections: &[SectionView]) -> String {
+    render_to_string(|out| write_views(out, config, sections))
+}
+
+fn write_views(out: &mut dyn Write, config: &Config, sections: &[SectionView]) -> io::Result<()> {
+    match config.format {
+        Format::Markdown => write_markdown(out, config, sections),
+        Format::Json => write_json(out, config, sections),
+        Format::Plain => write_plain(out, config, sections),
+        Format::Xml => write_xml(out, config, sections),
+        Format::Html => html::write_html(out, config, sections),
+    }
+}
+
+/// Renders just the file tree, as it appears at the top of a markdown document.
+pub fn render_tree(config: &Config, tree: &TreeItem) -> String {
+    let root = tree_root(config, &config.directory);
+    render_to_string(|out| print_tree(out, &root, tree, config.color))
+}
+
+/// Renders a single file the way it appears inside a document.
+pub fn render_file(config: &Config, file: &FileEntry) -> String {
+    let shown = match config.flatten {
+        true => flat_name(&file.path),
+        false => shown_path(config, display_base(config, &config.directory), &file.path),
+    };
+    render_file_as(config, &shown, file)
+}
+
+fn render_file_as(config: &Config, shown: &str, file: &FileEntry) -> String {
+    render_to_string(|out| match config.format {
+        Format::Markdown => write_markdown_file(out, shown, file),
+        Format::Json => Ok(serde_json::to_writer_pretty(out, file)?),
+        Format::Plain => write_plain_file(out, shown, file),
+        Format::Xml => write_xml_file(out, shown, file),
+        Format::Html => html::write_html_file(out, shown, file),
+    })
+}
+
+fn render_to_string(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+    let mut buffer = Vec::new();
+    write(&mut buffer).expect("writing to a Vec cannot fail");
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+fn is_plain_text_file(path: &Path, config: &Config) -> bool {
+    // Extensions with a known language are explicitly plain text, and so are
+    // scripts starting with a shebang
+    if language_by_extension(path, config).is_some() || shebang_interpreter(path).is_some() {
+        return true;
+    }
+
+    // Fall back to mime_guess for other files
+    match MimeGuess::from_path(path).first() {
+        Some(mime_type) => {
+            mime_type.type_() == "text"
+                || (mime_type.type_() == "application" && (mime_type.subtype() == "json"))
+        }
+        // Unknown or missing extension, so look at the bytes themselves
+        None => looks_like_text(path).unwrap_or(false),
+    }
+}
+
+/// Sniffs the start of a file the way git does: a NUL byte or invalid UTF-8
+/// within the first 8KB means the file is binary. Files starting with a
+/// UTF-16 byte order mark are text, NUL bytes and all.
+fn looks_like_text(path: &Path) -> io::Result<bool> {
+    const SNIFF_LEN: u64 = 8 * 1024;
+
+    let mut sample = Vec::new();
+    fs::File::open(path)?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut sample)?;
+
+    if utf16_bom(&sample).is_some() {
+        return Ok(true);
+    }
+    if sample.contains(&0) {
+        return Ok(false);
+    }
+    match std::str::from_utf8(&sample) {
+        Ok(_) => Ok(true),
+        // A multi-byte character cut off by the sample boundary is still text
+        Err(err) => Ok(err.error_len().is_none()),
+    }
+}
+
+/// Reads a file as UTF-8, or returns `None` when it is not and such files
+/// are skipped. A leading byte order mark is dropped, and files it marks as
+/// UTF-16 are decoded from that.
+fn read_file(path: &Path, encoding: Encoding) -> io::Result<Option<String>> {
+    let mut bytes = fs::read(path)?;
+    if let Some(big_endian) = utf16_bom(&bytes) {
+        return decode_utf16(&bytes[2..], big_endian, encoding);
+    }
+    if bytes.starts_with(UTF8_BOM) {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    match String::from_utf8(bytes) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) => match encoding {
+            Encoding::Lossy => Ok(Some(String::from_utf8_lossy(err.as_bytes()).into_owned())),
+            Encoding::Skip => Ok(None),
+            Encoding::Strict => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )),
+        },
+    }
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Whether `bytes` start with a UTF-16 byte order mark, and if so whether it
+/// marks big endian.
+fn utf16_bom(bytes: &[u8]) -> Option<bool> {
+    match bytes {
+        [0xFF, 0xFE, ..] => Some(false),
+        [0xFE, 0xFF, ..] => Some(true),
+        _ => None,
+    }
+}
+
+/// Decodes UTF-16 after its byte order mark, handling invalid text like
+/// [`read_file`] handles invalid UTF-8.
+fn decode_utf16(bytes: &[u8], big_endian: bool, encoding: Encoding) -> io::Result<Option<String>> {
+    let units = bytes.chunks_exact(2).map(|pair| match big_endian {
+        true => u16::from_be_bytes([pair[0], pair[1]]),
+        false => u16::from_le_bytes([pair[0], pair[1]]),
+    });
+    let mut text = String::with_capacity(bytes.len() / 2);
+    // A trailing odd byte is half a code unit
+    let odd = !bytes.len().is_multiple_of(2);
+    let mut invalid = odd;
+    for c in char::decode_utf16(units) {
+        text.push(c.unwrap_or_else(|_| {
+            invalid = true;
+            char::REPLACEMENT_CHARACTER
+        }));
+    }
+    if !invalid {
+        return Ok(Some(text));
+    }
+    match encoding {
+        Encoding::Lossy => {
+            if odd {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+            Ok(Some(text))
+        }
+        Encoding::Skip => Ok(None),
+        Encoding::Strict => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-16",
+        )),
+    }
+}
+
+/// Whether an exclude pattern matches `path`. A pattern without `/` matches
+/// any component of the path below `config.directory`, so `node_modules`
+/// excludes that directory at every level. Other patterns match the whole
+/// path relative to `config.directory` (anchored to it with a leading `/`),
+/// or the path as walked.
+fn should_exclude(path: &Path, config: &Config) -> bool {
+    exclude_matches(path, config, None)
+}
+
+/// Like [`should_exclude`], also marking in `matched` every pattern that
+/// matches, by its index.
+fn exclude_matches(path: &Path, config: &Config, matched: Option<&[Cell<bool>]>) -> bool {
+    if config.exclude_patterns.is_empty() {
+        return false;
+    }
+    let relative = path.strip_prefix(&config.directory).unwrap_or(path);
+    let names = path_names(relative);
+    let relative = glob_path(relative);
+    let walked = glob_path(path);
+
+    let matches = |pattern: &Pattern| {
+        if pattern.as_str().contains('/') {
+            matches_path(pattern, &relative, &walked)
+        } else {
+            names.iter().any(|name| pattern.matches(name))
+        }
+    };
+    match matched {
+        None => config.exclude_patterns.iter().any(matches),
+        Some(matched) => {
+            let mut excluded = false;
+            for (pattern, matched) in config.exclude_patterns.iter().zip(matched) {
+                if matches(pattern) {
+                    matched.set(true);
+                    excluded = true;
+                }
+            }
+            excluded
+        }
+    }
+}
+
+/// Whether `path` is inside one of the `--scope` prefixes, or is a directory
+/// leading to one.
+fn in_scope(path: &Path, config: &Config, is_dir: bool) -> bool {
+    if config.scopes.is_empty() {
+        return true;
+    }
+    let names = path_names(path.strip_prefix(&config.directory).unwrap_or(path));
+    config.scopes.iter().any(|scope| {
+        let scope = path_names(scope);
+        names.starts_with(&scope) || (is_dir && scope.starts_with(&names))
+    })
+}
+
+/// The names making up `path`, leaving out `.` and roots.
+fn path_names(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a pattern matches a path: `relative` to the directory, with a
+/// leading `/` to anchor it there, or as `walked`.
+fn matches_path(pattern: &Pattern, relative: &str, walked: &str) -> bool {
+    pattern.matches(relative)
+        || pattern.matches(&format!("/{}", relative))
+        || pattern.matches(walked)
+}
+
+/// `path` as patterns written with `/` expect it: joined by `/`, with `\`
+/// counting as `/` on every platform as for [`Config::strip_prefix`], and
+/// without `.` components. `./src\gen/a.rs` gives `src/gen/a.rs`.
+pub fn glob_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut glob = String::new();
+    for component in Path::new(&path).components() {
+        let part = match component {
+            Component::CurDir => continue,
+            Component::Prefix(prefix) => {
+                glob.push_str(&prefix.as_os_str().to_string_lossy());
+                continue;
+            }
+            Component::RootDir => {
+                glob.push('/');
+                continue;
+            }
+            Component::ParentDir | Component::Normal(_) => component.as_os_str().to_string_lossy(),
+        };
+        if !glob.is_empty() && !glob.ends_with('/') {
+            glob.push('/');
+        }
+        glob.push_str(&part);
+    }
+    glob
+}
+
+/// Files are included when no include patterns are given or one of them
+/// matches, and their language passes the language filters.
+fn should_include(path: &Path, config: &Config) -> bool {
+    let matched = config.include_patterns.is_empty() || {
+        let relative = glob_path(path.strip_prefix(&config.directory).unwrap_or(path));
+        let walked = glob_path(path);
+        config
+            .include_patterns
+            .iter()
+            .any(|pattern| matches_path(pattern, &relative, &walked))
+    };
+    matched
+        && (config.only_languages.is_empty() && config.skip_languages.is_empty() || {
+            let language = detect_language(path, config);
+            (config.only_languages.is_empty() || config.only_languages.contains(&language))
+                && !config.skip_languages.contains(&language)
+        })
+}
+
+/// Builds the tree of the directory `path`, the root of the walk. Directories
+/// are walked depth first with an explicit stack, so deep trees cannot
+/// overflow the call stack.
+fn build_tree_item(
+    path: &Path,
+    config: &Config,
+    ignores: &IgnoreStack,
+    matched: &[Cell<bool>],
+) -> io::Result<TreeItem> {
+    // Only errors on the root abort the run, list_dir reports the rest
+    let kind = match fs::metadata(path)?.is_dir() {
+        true => Kind::Dir,
+        false => Kind::File,
+    };
+    let root = TreeItem::new(&entry_name(path), kind);
+    if kind == Kind::File || exclude_matches(path, config, Some(matched)) {
+        return Ok(root);
+    }
+    let entries = fs::read_dir(path)?.filter_map(Result::ok).collect();
+    let mut stack = vec![DirFrame::new(root, path, entries, ignores, &[], config, 0)];
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(entry) = frame.entries.next() else {
+            let mut done = stack.pop().expect("The stack is not empty");
+            if config.tree_sizes {
+                let sizes = done.node.children.iter().filter_map(|child| child.size);
+                done.node.size = Some(sizes.sum());
+            }
+            let Some(parent) = stack.last_mut() else {
+                return Ok(done.node);
+            };
+            if !done.node.children.is_empty() || config.keep_empty_dirs {
+                parent.node.add_child(done.node);
+            }
+            continue;
+        };
+        let child_path = entry.path();
+        let Some(is_dir) = visit_entry(&entry, config, &frame.ancestors) else {
+            continue;
+        };
+        if exclude_matches(&child_path, config, Some(matched))
+            || frame.ignores.is_ignored(&child_path, is_dir)
+            || !in_scope(&child_path, config, is_dir)
+        {
+            continue;
+        }
+        let kind = match is_dir {
+            true => Kind::Dir,
+            false => Kind::File,
+        };
+        let mut child = TreeItem::new(&entry_name(&child_path), kind);
+        if !is_dir {
+            if should_include(&child_path, config)
+                && passes_metadata_filters(&child_path, config).unwrap_or(false)
+            {
+                if config.tree_sizes {
+                    child.size = fs::metadata(&child_path).map(|m| m.len()).ok();
+                }
+                frame.node.add_child(child);
+            }
+            continue;
+        }
+
+        let depth = frame.depth + 1;
+        if !within_depth(config, depth) {
+            child.truncated = true;
+            frame.node.add_child(child);
+            continue;
+        }
+        match fs::read_dir(&child_path) {
+            Ok(entries) => {
+                let entries = entries.filter_map(Result::ok).collect();
+                let child = DirFrame::new(
+                    child,
+                    &child_path,
+                    entries,
+                    &frame.ignores,
+                    &frame.ancestors,
+                    config,
+                    depth,
+                );
+                stack.push(child);
+            }
+            // Shown, so it is clear why the directory looks empty
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                child.permission_denied = true;
+                frame.node.add_child(child);
+            }
+            Err(_) if config.keep_empty_dirs => frame.node.add_child(child),
+            Err(_) => {}
+        }
+    }
+    unreachable!("The walk returns when the root is done")
+}
+
+/// A directory being walked: what has been built for it so far, its
+/// remaining entries and the state its subdirectories inherit.
+struct DirFrame<T> {
+    node: T,
+    entries: std::vec::IntoIter<fs::DirEntry>,
+    ignores: IgnoreStack,
+    ancestors: Vec<PathBuf>,
+    /// Levels below `config.directory`.
+    depth: usize,
+}
+
+impl<T> DirFrame<T> {
+    fn new(
+        node: T,
+        dir: &Path,
+        mut entries: Vec<fs::DirEntry>,
+        ignores: &IgnoreStack,
+        ancestors: &[PathBuf],
+        config: &Config,
+        depth: usize,
+    ) -> Self {
+        sort_entries(&mut entries);
+        DirFrame {
+            node,
+            entries: entries.into_iter(),
+            ignores: ignores.descend(dir),
+            ancestors: with_ancestor(ancestors, dir, config),
+            depth,
+        }
+    }
+}
+
+/// The name of a path as shown in the tree.
+fn entry_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// ANSI styles of the tree with [`Config::color`].
+const DIRECTORY_STYLE: &str = "\x1b[1;34m";
+const DIM_STYLE: &str = "\x1b[2m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+/// `text` in an ANSI `style` when `color` is set.
+fn paint(text: &str, style: &str, color: bool) -> String {
+    match color {
+        true => format!("{}{}{}", style, text, RESET_STYLE),
+        false => text.to_string(),
+    }
+}
+
+fn print_tree_item(
+    out: &mut dyn Write,
+    item: &TreeItem,
+    prefix: &str,
+    is_last: bool,
+    color: bool,
+) -> io::Result<()> {
+    let connector = if is_last { "└── " } else { "├── " };
+    let lines = paint(&format!("{}{}", prefix, connector), DIM_STYLE, color);
+    // Directories end in `/`, as in `tree -F`
+    let name = match item.kind {
+        Kind::Dir => paint(&format!("{}/", item.name), DIRECTORY_STYLE, color),
+        Kind::File => item.name.clone(),
+    };
+    if item.truncated {
+        writeln!(out, "{}{} (...)", lines, name)
+    } else if item.permission_denied {
+        writeln!(out, "{}{} <permission denied>", lines, name)
+    } else if let Some(size) = item.size {
+        let size = paint(&format!("({})", format_size(size)), DIM_STYLE, color);
+        writeln!(out, "{}{} {}", lines, name, size)
+    } else {
+        writeln!(out, "{}{}", lines, name)
+    }
+}
+
+fn print_tree(out: &mut dyn Write, path: &Path, tree: &TreeItem, color: bool) -> io::Result<()> {
+    let root = paint(&path.display().to_string(), DIRECTORY_STYLE, color);
+    match tree.size {
+        Some(size) => {
+            let size = paint(&format!("({})", format_size(size)), DIM_STYLE, color);
+            writeln!(out, "{} {}", root, size)?
+        }
+        None => writeln!(out, "{}", root)?,
+    }
+    // Items still to print with their prefix and whether they are the last
+    // child, the next one on top
+    let mut stack = vec![];
+    push_children(&mut stack, tree, "");
+    while let Some((item, prefix, is_last)) = stack.pop() {
+        print_tree_item(out, item, &prefix, is_last, color)?;
+        let prefix = if is_last {
+            format!("{}    ", prefix)
+        } else {
+            format!("{}│   ", prefix)
+        };
+        push_children(&mut stack, item, &prefix);
+    }
+
+    Ok(())
+}
+
+fn push_children<'a>(
+    stack: &mut Vec<(&'a TreeItem, String, bool)>,
+    item: &'a TreeItem,
+    prefix: &str,
+) {
+    let last = item.children.len().saturating_sub(1);
+    for (i, child) in item.children.iter().enumerate().rev() {
+        stack.push((child, prefix.to_string(), i == last));
+    }
+}
+
+/// Built-in fence languages by file extension.
+const LANGUAGES: &[(&str, &str)] = &[
+    // Web development
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("js", "javascript"),
+    ("jsx", "javascript"),
+    ("json", "json"),
+    ("ipynb", "json"),
+    ("html", "html"),
+    ("htm", "html"),
+    ("css", "css"),
+    ("scss", "scss"),
+    ("sass", "scss"),
+    ("graphql", "graphql"),
+    // Template files
+    ("twig", "twig"),
+    ("ejs", "ejs"),
+    ("hbs", "handlebars"),
+    ("vue", "vue"),
+    ("svelte", "svelte"),
+    // Config files
+    ("yml", "yaml"),
+    ("yaml", "yaml"),
+    ("toml", "toml"),
+    ("ini", "ini"),
+    ("env", "dotenv"),
+    ("xml", "xml"),
+    ("tf", "hcl"),
+    ("proto", "protobuf"),
+    // Documentation
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+    ("txt", ""),
+    ("rst", "restructuredtext"),
+    // Other programming languages
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("php", "php"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("kts", "kotlin"),
+    ("scala", "scala"),
+    ("go", "go"),
+    ("rs", "rust"),
+    ("c", "c"),
+    ("cpp", "cpp"),
+    ("cc", "cpp"),
+    ("cxx", "cpp"),
+    ("h", "cpp"),
+    ("hpp", "cpp"),
+    ("cs", "csharp"),
+    ("swift", "swift"),
+    ("m", "objectivec"),
+    ("zig", "zig"),
+    ("dart", "dart"),
+    ("lua", "lua"),
+    ("pl", "perl"),
+    ("r", "r"),
+    ("ex", "elixir"),
+    ("exs", "elixir"),
+    ("erl", "erlang"),
+    ("hs", "haskell"),
+    ("ml", "ocaml"),
+    ("clj", "clojure"),
+    ("sql", "sql"),
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("ps1", "powershell"),
+];
+
+/// Languages of the interpreters named by shebang lines, for scripts without
+/// an extension. Version suffixes such as `python3.12` are ignored.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "python"),
+    ("node", "javascript"),
+    ("deno", "typescript"),
+    ("ruby", "ruby"),
+    ("perl", "perl"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("Rscript", "r"),
+    ("elixir", "elixir"),
+    ("escript", "erlang"),
+    ("runhaskell", "haskell"),
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("dash", "bash"),
+    ("ksh", "bash"),
+    ("zsh", "zsh"),
+    ("fish", "fish"),
+    ("pwsh", "powershell"),
+];
+
+/// The fence language for `path`, from its extension or else its shebang, or
+/// none for unknown files.
+fn detect_language(path: &Path, config: &Config) -> String {
+    language_by_extension(path, config)
+        .or_else(|| language_by_shebang(path))
+        .unwrap_or_default()
+}
+
+/// The language of the interpreter named by the `#!` line of an extensionless
+/// script, such as `#!/usr/bin/env python3`.
+fn language_by_shebang(path: &Path) -> Option<String> {
+    let interpreter = shebang_interpreter(path)?;
+    let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(interpreter, _)| *interpreter == name)
+        .map(|(_, language)| language.to_string())
+}
+
+/// The program a script without an extension starts with `#!`, looking past
+/// `env` and its options and variables.
+fn shebang_interpreter(path: &Path) -> Option<String> {
+    if path.extension().is_some() {
+        return None;
+    }
+    let mut start = Vec::new();
+    fs::File::open(path)
+        .ok()?
+        .take(256)
+        .read_to_end(&mut start)
+        .ok()?;
+    let line = start.strip_prefix(b"#!")?.split(|&b| b == b'\n').next()?;
+    let line = String::from_utf8_lossy(line);
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    Some(program.to_string())
+}
+
+/// Looks the extension of `path` up in `config.languages`, then in the
+/// built-in [`LANGUAGES`].
+fn language_by_extension(path: &Path, config: &Config) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    if let Some(language) = config.languages.get(&extension) {
+        return Some(language.clone());
+    }
+    LANGUAGES
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, language)| language.to_string())
+}
+
+/// Lists the selected files under the directory `root` in tree order, walking
+/// depth first with an explicit stack like [`build_tree_item`].
+fn list_dir(
+    root: &Path,
+    config: &Config,
+    ignores: &IgnoreStack,
+    walk: &mut Walk,
+) -> io::Result<()> {
+    if !root.is_dir() {
+        return Ok(());
+    }
+    let entries = read_entries(root, config)?;
+    walk.dirs.push(root.to_path_buf());
+    let Walk {
+        files,
+        excluded,
+        dirs,
+    } = walk;
+    let mut stack = vec![DirFrame::new((), root, entries, ignores, &[], config, 0)];
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(entry) = frame.entries.next() else {
+            stack.pop();
+            continue;
+        };
+        let entry_path = entry.path();
+        let Some(is_dir) = visit_entry(&entry, config, &frame.ancestors) else {
+            if !config.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                record_excluded(config, excluded, &entry_path, ExclusionReason::Hidden);
+            }
+            continue;
+        };
+        if should_exclude(&entry_path, config) {
+            explain(
+                config,
+                format_args!(
+                    "Skipping {}, which is excluded",
+                    warned_path(config, &entry_path)
+                ),
+            );
+            record_excluded(config, excluded, &entry_path, ExclusionReason::Pattern);
+            continue;
+        }
+        if frame.ignores.is_ignored(&entry_path, is_dir) {
+            explain(
+                config,
+                format_args!(
+                    "Skipping {}, which is ignored",
+                    warned_path(config, &entry_path)
+                ),
+            );
+            record_excluded(config, excluded, &entry_path, ExclusionReason::Ignored);
+            continue;
+        }
+        if !in_scope(&entry_path, config, is_dir) {
+            explain(
+                config,
+                format_args!(
+                    "Skipping {}, which is outside the scope",
+                    warned_path(config, &entry_path)
+                ),
+            );
+            record_excluded(config, excluded, &entry_path, ExclusionReason::Filtered);
+            continue;
+        }
+        if !is_dir {
+            if !should_include(&entry_path, config) {
+                explain(
+                    config,
+                    format_args!(
+                        "Skipping {}, which is not included",
+                        warned_path(config, &entry_path)
+                    ),
+                );
+                record_excluded(config, excluded, &entry_path, ExclusionReason::Filtered);
+                continue;
+            }
+            if !config.include_lockfiles && is_lockfile(&entry_path) {
+                explain(
+                    config,
+                    format_args!(
+                        "Skipping {}, which is a lockfile",
+                        warned_path(config, &entry_path)
+                    ),
+                );
+                record_excluded(config, excluded, &entry_path, ExclusionReason::Lockfile);
+                continue;
+            }
+            match passes_metadata_filters(&entry_path, config) {
+                Ok(true) => {
+                    let selected = files.len();
+                    select_file(entry_path.clone(), config, files);
+                    if files.len() == selected && entry_path.to_str().is_some() {
+                        record_excluded(config, excluded, &entry_path, ExclusionReason::Binary);
+                    }
+                    // Stop the walk early rather than after all of `/`
+                    check_file_count(config, files.len())?;
+                }
+                Ok(false) => {
+                    explain(
+                        config,
+                        format_args!(
+                            "Skipping {}, which is filtered out by its size or modification time",
+                            warned_path(config, &entry_path)
+                        ),
+                    );
+                    record_excluded(config, excluded, &entry_path, ExclusionReason::Size);
+                }
+                Err(e) => warn(
+                    config,
+                    format_args!(
+                        "Warning: Skipping {}, could not read its metadata: {}",
+                        warned_path(config, &entry_path),
+                        e
+                    ),
+                ),
+            }
+            continue;
+        }
+
+        let depth = frame.depth + 1;
+        if !within_depth(config, depth) {
+            explain(
+                config,
+                format_args!(
+                    "Skipping {}, which is below the depth limit",
+                    warned_path(config, &entry_path)
+                ),
+            );
+            record_excluded(config, excluded, &entry_path, ExclusionReason::Filtered);
+            continue;
+        }
+        match read_entries(&entry_path, config) {
+            Ok(entries) => {
+                dirs.push(entry_path.clone());
+                let child = DirFrame::new(
+                    (),
+                    &entry_path,
+                    entries,
+                    &frame.ignores,
+                    &frame.ancestors,
+                    config,
+                    depth,
+                );
+                stack.push(child);
+            }
+            Err(e) => warn(
+                config,
+                format_args!(
+                    "Warning: Could not read directory {}: {}",
+                    warned_path(config, &entry_path),
+                    e
+                ),
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Records `path` as left out for `reason` when
+/// [`Config::show_excluded_summary`] is set, counting the files below it.
+fn record_excluded(
+    config: &Config,
+    excluded: &mut Vec<Excluded>,
+    path: &Path,
+    reason: ExclusionReason,
+) {
+    if !config.show_excluded_summary {
+        return;
+    }
+    let (files, bytes) = WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .fold((0, 0), |(files, bytes), entry| {
+            let size = entry.metadata().map_or(0, |metadata| metadata.len());
+            (files + 1, bytes + size)
+        });
+    excluded.push(Excluded {
+        path: path.to_path_buf(),
+        reason,
+        files,
+        bytes,
+    });
+}
+
+/// Reads the entries of a directory, reporting the ones that fail.
+fn read_entries(dir: &Path, config: &Config) -> io::Result<Vec<fs::DirEntry>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        match entry {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn(config, format_args!("Error reading entry: {}", e)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Prints a warning about a skipped or unreadable file, unless quiet.
+fn warn(config: &Config, message: fmt::Arguments) {
+    if config.verbosity > Verbosity::Quiet {
+        eprintln!("{}", message);
+    }
+}
+
+/// `path` as warnings about it show it, see [`display_path`].
+fn warned_path(config: &Config, path: &Path) -> String {
+    display_path(config, &config.directory, path)
+}
+
+/// Prints why an entry is left out, when verbose.
+fn explain(config: &Config, message: fmt::Arguments) {
+    if config.verbosity == Verbosity::Verbose {
+        eprintln!("{}", message);
+    }
+}
+
+/// Whether the contents of a directory `depth` levels below the root are read.
+fn within_depth(config: &Config, depth: usize) -> bool {
+    config.max_depth.is_none_or(|max_depth| depth <= max_depth)
+}
+
+/// Decides whether a directory entry is walked, and if so whether it is a
+/// directory. Hidden entries are skipped unless enabled, symlinks unless
+/// followed, and a followed link may not lead back to a directory it is
+/// inside of.
+fn visit_entry(entry: &fs::DirEntry, config: &Config, ancestors: &[PathBuf]) -> Option<bool> {
+    if !config.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+        return None;
+    }
+    let path = entry.path();
+    if !entry
+        .file_type()
+        .is_ok_and(|file_type| file_type.is_symlink())
+    {
+        return Some(path.is_dir());
+    }
+    if !config.follow_symlinks {
+        return None;
+    }
+    if !path.is_dir() {
+        // Broken links are skipped
+        return path.exists().then_some(false);
+    }
+    let target = fs::canonicalize(&path).ok()?;
+    (!ancestors.contains(&target)).then_some(true)
+}
+
+/// The canonical paths of the directories above an entry, which are only
+/// tracked when following symlinks.
+fn with_ancestor(ancestors: &[PathBuf], dir: &Path, config: &Config) -> Vec<PathBuf> {
+    let mut ancestors = ancestors.to_vec();
+    if config.follow_symlinks {
+        if let Ok(dir) = fs::canonicalize(dir) {
+            ancestors.push(dir);
+        }
+    }
+    ancestors
+}
+
+/// Orders directory entries the same way for the tree and the listing:
+/// directories first, then files, each sorted by name.
+fn sort_entries(entries: &mut [fs::DirEntry]) {
+    entries.sort_by_cached_key(|entry| (!entry.path().is_dir(), entry.file_name()));
+}
+
+/// Selects the files of `config.files` that pass the exclude and include rules.
+fn list_files(paths: &[PathBuf], config: &Config) -> Vec<PathBuf> {
+    let ignores = IgnoreStack::new(config, false);
+    let mut files = vec![];
+    for path in paths {
+        let path = config.directory.join(path);
+        if !path.is_file() {
+            warn(
+                config,
+                format_args!("Warning: {} is not a file", warned_path(config, &path)),
+            );
+        } else if is_listed(&path, config, &ignores) {
+            select_file(path, config, &mut files);
+        }
+    }
+    files
+}
+
+/// Explicitly listed files skip `.gitignore`, but not `.promptifyignore`.
+fn is_listed(path: &Path, config: &Config, ignores: &IgnoreStack) -> bool {
+    !should_exclude(path, config)
+        && !ignores.is_ignored(path, false)
+        && should_include(path, config)
+        && passes_metadata_filters(path, config).unwrap_or(false)
+}
+
+/// Whether a file was modified after `config.modified_since` and its size is
+/// within `config.min_size` and `config.max_size`, as far as they are set.
+fn passes_metadata_filters(path: &Path, config: &Config) -> io::Result<bool> {
+    if config.modified_since.is_none() && config.min_size.is_none() && config.max_size.is_none() {
+        return Ok(true);
+    }
+    let metadata = fs::metadata(path)?;
+    let size = metadata.len();
+    let recent = match config.modified_since {
+        Some(threshold) => metadata.modified()? > threshold,
+        None => true,
+    };
+    Ok(recent
+        && config.min_size.is_none_or(|min_size| size >= min_size)
+        && config.max_size.is_none_or(|max_size| size <= max_size))
+}
+
+/// Builds a tree holding exactly the given relative paths.
+fn tree_from_paths<'a>(config: &Config, paths: impl Iterator<Item = &'a PathBuf>) -> TreeItem {
+    let mut tree = TreeItem::new(&entry_name(&config.directory), Kind::Dir);
+    if config.tree_sizes {
+        tree.size = Some(0);
+    }
+    for path in paths {
+        // Every node on the way down adds the size of the file
+        let size = config.tree_sizes.then(|| {
+            fs::metadata(config.directory.join(path)).map_or(0, |metadata| metadata.len())
+        });
+        let mut node = &mut tree;
+        if let Some(size) = size {
+            *node.size.get_or_insert(0) += size;
+        }
+        let names: Vec<_> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            // Every name but the last one is a directory on the way
+            let kind = match i + 1 == names.len() {
+                true => Kind::File,
+                false => Kind::Dir,
+            };
+            let index = match node.children.iter().position(|child| child.name == *name) {
+                Some(index) => index,
+                None => {
+                    node.add_child(TreeItem::new(name, kind));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index];
+            if let Some(size) = size {
+                *node.size.get_or_insert(0) += size;
+            }
+        }
+    }
+    tree
+}
+
+/// Extensions of files that are binary for sure, skipped without a warning.
+#[rustfmt::skip]
+const BINARY_EXTENSIONS: &[&str] = &[
+    // Images and design files
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "icns", "webp", "avif", "tif", "tiff", "psd",
+    // Fonts
+    "woff", "woff2", "ttf", "otf", "eot",
+    // Audio and video
+    "mp3", "wav", "ogg", "flac", "mp4", "webm", "mov", "avi", "mkv",
+    // Archives
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "tar", "jar", "war",
+    // Documents
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx",
+    // Compiled code and data
+    "exe", "dll", "so", "dylib", "a", "o", "obj", "lib", "bin", "class", "pyc", "pyo", "wasm",
+    "db", "sqlite", "sqlite3",
+];
+
+/// Names of the lockfiles of package managers, generated and rarely useful
+/// in a prompt.
+#[rustfmt::skip]
+const LOCKFILES: &[&str] = &[
+    "Cargo.lock", "package-lock.json", "npm-shrinkwrap.json", "yarn.lock", "pnpm-lock.yaml",
+    "bun.lock", "bun.lockb", "deno.lock", "composer.lock", "Gemfile.lock", "poetry.lock",
+    "Pipfile.lock", "uv.lock", "pdm.lock", "go.sum", "flake.lock", "mix.lock", "pubspec.lock",
+    "Podfile.lock", "Package.resolved", "packages.lock.json", "gradle.lockfile",
+];
+
+fn is_lockfile(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| LOCKFILES.contains(&name))
+}
+
+/// Whether `path` is of a type that is never text, by its extension or its
+/// MIME type, such as an image or a font. SVG images are text.
+fn is_known_binary(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+    let extension = extension.to_lowercase();
+    if BINARY_EXTENSIONS.contains(&extension.as_str()) {
+        return true;
+    }
+    MimeGuess::from_path(path).first().is_some_and(|mime| {
+        matches!(mime.type_().as_str(), "image" | "audio" | "video" | "font")
+            && mime.subtype() != "svg"
+    })
+}
+
+/// Adds a selected file to `files`, warning when it is not plaintext.
+fn select_file(path: PathBuf, config: &Config, files: &mut Vec<PathBuf>) {
+    if is_plain_text_file(&path, config) || is_included_binary(&path, config) {
+        if path.to_str().is_some() {
+            files.push(path);
+        }
+    } else if !is_known_binary(&path) && looks_like_text(&path).unwrap_or(true) {
+        // Known binary types and files that sniff as binary are skipped silently
+        warn(
+            config,
+            format_args!(
+                "Warning: Skipping {}, which is not a known plaintext file",
+                warned_path(config, &path)
+            ),
+        );
+    }
+}
+
+/// Size limit of binary files when `max_file_size` is not set.
+const DEFAULT_MAX_BINARY_SIZE: u64 = 1024 * 1024;
+
+/// Whether `path` has one of the extensions of [`Config::include_binary`].
+fn is_included_binary(path: &Path, config: &Config) -> bool {
+    path.extension().is_some_and(|ext| {
+        config
+            .include_binary
+            .contains(&ext.to_string_lossy().to_lowercase())
+    })
+}
+
+fn mime_type(path: &Path) -> String {
+    MimeGuess::from_path(path)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string()
+}
+
+/// Reads a selected file, or returns `None` when it is skipped for not being
+/// valid UTF-8.
+fn read_entry(path: PathBuf, config: &Config) -> Option<FileEntry> {
+    let metadata = fs::metadata(&path).ok();
+    let binary = is_included_binary(&path, config) && !is_plain_text_file(&path, config);
+    let max_file_size = match binary {
+        true => Some(config.max_file_size.unwrap_or(DEFAULT_MAX_BINARY_SIZE)),
+        false => config.max_file_size,
+    };
+    let oversized = max_file_size.and_then(|limit| {
+        metadata
+            .as_ref()
+            .map(|metadata| metadata.len())
+            .filter(|&size| size > limit)
+            .map(|size| (size, limit))
+    });
+    let mut language = detect_language(&path, config);
+    let mut mime = None;
+    let ranges = path
+        .strip_prefix(&config.directory)
+        .ok()
+        .and_then(|relative| config.line_ranges.get(relative))
+        .cloned()
+        .unwrap_or_default();
+    // Set to the ranges once they are extracted
+    let mut lines = vec![];
+    let mut line_count = None;
+    let content = match oversized {
+        Some((size, limit)) => Ok(format!(
+            "// file omitted ({} > {} limit)",
+            format_size(size),
+            format_size(limit)
+        )),
+        None if binary => fs::read(&path).map(|bytes| {
+            language = "base64".to_string();
+            mime = Some(mime_type(&path));
+            BASE64_STANDARD.encode(bytes)
+        }),
+        None => match read_text(&path, metadata.as_ref(), config) {
+            Ok(Some(content)) if config.skip_minified && is_minified(&content) => {
+                warn(
+                    config,
+                    format_args!(
+                        "Warning: Skipping {}, which looks minified",
+                        warned_path(config, &path)
+                    ),
+                );
+                return None;
+            }
+            Ok(Some(mut content)) if !ranges.is_empty() => {
+                let total = content.lines().count();
+                line_count = config.line_counts.then_some(total);
+                if let Some(range) = ranges.iter().find(|range| range.start > total) {
+                    warn(
+                        config,
+                        format_args!(
+                            "Warning: {} has {} lines, lines {} are past its end",
+                            warned_path(config, &path),
+                            total,
+                            range
+                        ),
+                    );
+                }
+                // Numbered here, while the real line numbers are known
+                let numbered = config
+                    .line_numbers
+                    .is_some_and(|min_lines| total >= min_lines);
+                content = extract_lines(&content, &ranges, numbered);
+                lines = ranges;
+                Ok(transform_content(content, &path, &language, config, true))
+            }
+            Ok(Some(mut content)) => {
+                line_count = config.line_counts.then(|| content.lines().count());
+                if notebook::is_notebook(&path) {
+                    if let Some(cells) = notebook::extract_cells(&content, config.notebook_outputs)
+                    {
+                        content = cells;
+                        language = "markdown".to_string();
+                    }
+                }
+                if table::is_table(&path) {
+                    let rows = config.csv_rows.unwrap_or(table::DEFAULT_TABLE_ROWS);
+                    if let Some(summary) = table::summarize(&content, rows) {
+                        content = summary;
+                    }
+                }
+                Ok(transform_content(content, &path, &language, config, false))
+            }
+            Ok(None) => {
+                warn(
+                    config,
+                    format_args!(
+                        "Warning: Skipping {}, which is not valid UTF-8",
+                        warned_path(config, &path)
+                    ),
+                );
+                return None;
+            }
+            Err(e) => Err(e),
+        },
+    };
+
+    let mut entry = FileEntry {
+        mime,
+        language,
+        path,
+        content: None,
+        error: None,
+        metadata: None,
+        line_count,
+        lines,
+    };
+    if config.metadata {
+        entry.metadata = metadata.map(|metadata| FileMetadata {
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(format_date),
+        });
+    }
+    match content {
+        Ok(content) => entry.content = Some(content),
+        Err(e) => {
+            warn(
+                config,
+                format_args!(
+                    "Warning: Could not read {}: {}",
+                    warned_path(config, &entry.path),
+                    e
+                ),
+            );
+            if config.no_content_on_error {
+                return None;
+            }
+            entry.error = Some(e.to_string());
+        }
+    }
+    Some(entry)
+}
+
+/// Average line length in bytes from which a file counts as minified.
+const MINIFIED_LINE_LENGTH: usize = 500;
+
+/// Whether `content` looks like a minified bundle or a data dump, judging by
+/// its average line length.
+fn is_minified(content: &str) -> bool {
+    let lines = content.lines().count().max(1);
+    content.len() / lines >= MINIFIED_LINE_LENGTH
+}
+
+/// Reads a text file, through the cache if there is one.
+fn read_text(
+    path: &Path,
+    metadata: Option<&fs::Metadata>,
+    config: &Config,
+) -> io::Result<Option<String>> {
+    match &config.cache_dir {
+        Some(cache_dir) => {
+            // Lossy contents must not be served to a strict run
+            let variant = format!("{:?}", config.encoding);
+            cache::read_cached(cache_dir, path, metadata, &variant, || {
+                read_file(path, config.encoding)
+            })
+        }
+        None => read_file(path, config.encoding),
+    }
+}
+
+/// Parses a point in time given as a UTC `YYYY-MM-DD` date, or as a duration
+/// before now such as `30m`, `12h`, `7d` or `2w`.
+pub fn parse_time(value: &str) -> Result<SystemTime, String> {
+    let value = value.trim();
+    let invalid = || format!("invalid time '{}', expected e.g. 7d or 2024-01-01", value);
+
+    if let [year, month, day] = value.split('-').collect::<Vec<_>>()[..] {
+        let year: i64 = year.parse().map_err(|_| invalid())?;
+        let month: i64 = month.parse().map_err(|_| invalid())?;
+        let day: i64 = day.parse().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err(invalid());
+        }
+        // Days since the epoch from a civil date, see
+        // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y.rem_euclid(400);
+        let mp = (month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        let secs = days * 86_400;
+        return Ok(match u64::try_from(secs) {
+            Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+            Err(_) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+        });
+    }
+
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit: u64 = match &value[digits.len()..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let amount: u64 = digits.parse().map_err(|_| invalid())?;
+    let ago = Duration::from_secs(amount.saturating_mul(unit));
+    Ok(SystemTime::now().checked_sub(ago).unwrap_or(UNIX_EPOCH))
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SystemTime>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_time(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Formats a timestamp as a UTC `YYYY-MM-DD` date.
+fn format_date(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+
+    // Civil date from days since the epoch, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = secs.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats a timestamp as a UTC `YYYY-MM-DDTHH:MM:SSZ` date and time.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    let time_of_day = secs.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(time),
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+/// Applies the configured content transformations to a file that was read.
+/// Content that is `numbered` already, like extracted line ranges, is not
+/// numbered again.
+fn transform_content(
+    mut content: String,
+    path: &Path,
+    language: &str,
+    config: &Config,
+    numbered: bool,
+) -> String {
+    if let Some(transform) = config
+        .transforms
+        .iter()
+        .find(|transform| transform_applies(&transform.pattern, path, config))
+    {
+        match run_transform(&transform.command, &content) {
+            Ok(transformed) => content = transformed,
+            Err(e) => warn(
+                config,
+                format_args!(
+                    "Warning: Keeping the contents of {}, '{}' failed: {}",
+                    warned_path(config, path),
+                    transform.command,
+                    e
+                ),
+            ),
+        }
+    }
+    if config.redact {
+        content = redact::redact(&content, assignments(path, language));
+    }
+    if config.summary_only {
+        if let Some(summary) = summary::summarize(&content, language) {
+            content = summary;
+        }
+    }
+    if config.strip_comments {
+        if let Some(stripped) = comments::strip_comments(&content, language) {
+            content = stripped;
+        }
+    }
+    if config.minify {
+        if let Some(minified) = minify::minify(&content, language) {
+            content = minified;
+        }
+    }
+    if config.trim {
+        content = trim_blank_lines(&content);
+    }
+    if let Some(min_lines) = config.line_numbers.filter(|_| !numbered) {
+        if content.lines().count() >= min_lines {
+            content = number_lines(&content);
+        }
+    }
+    // After numbering, so the kept lines show their real line numbers
+    match config.truncate_lines {
+        Some(lines) => truncate_middle(content, lines),
+        None => content,
+    }
+}
+
+fn transform_applies(pattern: &Pattern, path: &Path, config: &Config) -> bool {
+    let relative = path.strip_prefix(&config.directory).unwrap_or(path);
+    match pattern.as_str().contains('/') {
+        true => pattern.matches(&glob_path(relative)),
+        false => relative
+            .file_name()
+            .is_some_and(|name| pattern.matches(&name.to_string_lossy())),
+    }
+}
+
+/// Pipes `content` through a shell `command`, returning what it prints. A
+/// command that fails or prints anything but UTF-8 is an error.
+fn run_transform(command: &str, content: &str) -> io::Result<String> {
+    let mut shell = match cfg!(windows) {
+        true => process::Command::new("cmd"),
+        false => process::Command::new("sh"),
+    };
+    shell
+        .arg(if cfg!(windows) { "/C" } else { "-c" })
+        .arg(command);
+    let mut child = shell
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Piped above");
+    // Fed from another thread, so a command printing before it has read
+    // everything cannot block on a full pipe
+    let output = thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(content.as_bytes()));
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(match stderr.lines().next() {
+            Some(line) if !line.trim().is_empty() => line.trim().to_string(),
+            _ => output.status.to_string(),
+        }));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "its output is not valid UTF-8"))
+}
+
+/// Strips trailing whitespace from every line and blank lines from both ends,
+/// and collapses runs of three or more blank lines into one.
+fn trim_blank_lines(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().map(str::trim_end).collect();
+    let Some(first) = lines.iter().position(|line| !line.is_empty()) else {
+        return String::new();
+    };
+    let last = lines
+        .iter()
+        .rposition(|line| !line.is_empty())
+        .unwrap_or(first);
+
+    let mut trimmed = String::with_capacity(content.len());
+    let mut blank_run = 0;
+    for (i, line) in lines[first..=last].iter().enumerate() {
+        if line.is_empty() {
+            blank_run += 1;
+            continue;
+        }
+        let blanks = if blank_run >= 3 { 1 } else { blank_run };
+        if i > 0 {
+            trimmed.push_str(&"\n".repeat(blanks + 1));
+        }
+        trimmed.push_str(line);
+        blank_run = 0;
+    }
+    if content.ends_with('\n') {
+        trimmed.push('\n');
+    }
+    trimmed
+}
+
+/// The lines of `content` in `ranges`, with a `...` line between ranges, and
+/// prefixed with their line numbers in the whole file when `numbered`.
+fn extract_lines(content: &str, ranges: &[LineRange], numbered: bool) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let width = ranges
+        .iter()
+        .map(|range| range.end.min(lines.len()))
+        .max()
+        .unwrap_or(0)
+        .to_string()
+        .len();
+    let mut extracted = vec![];
+    for range in ranges {
+        if range.start > lines.len() {
+            continue;
+        }
+        if !extracted.is_empty() {
+            extracted.push("...".to_string());
+        }
+        for (i, line) in lines[range.start - 1..range.end.min(lines.len())]
+            .iter()
+            .enumerate()
+        {
+            extracted.push(match (numbered, *line) {
+                (false, _) => line.to_string(),
+                (true, "") => format!("{:>width$} |", range.start + i),
+                (true, line) => format!("{:>width$} | {}", range.start + i, line),
+            });
+        }
+    }
+    let mut extracted = extracted.join("\n");
+    if !extracted.is_empty() {
+        extracted.push('\n');
+    }
+    extracted
+}
+
+/// Keeps the first and last `lines` lines of longer content, replacing the
+/// middle with a marker.
+fn truncate_middle(content: String, lines: usize) -> String {
+    let all: Vec<&str> = content.lines().collect();
+    if all.len() <= 2 * lines {
+        return content;
+    }
+    let omitted = all.len() - 2 * lines;
+    let mut truncated = all[..lines].join("\n");
+    if lines > 0 {
+        truncated.push('\n');
+    }
+    truncated.push_str(&format!("... ({} lines omitted) ...", omitted));
+    for line in &all[all.len() - lines..] {
+        truncated.push('\n');
+        truncated.push_str(line);
+    }
+    if content.ends_with('\n') {
+        truncated.push('\n');
+    }
+    truncated
+}
+
+/// Which values of a file `--redact` treats as assignments.
+fn assignments(path: &Path, language: &str) -> redact::Assignments {
+    // `.env`, `.env.local`, ... have no `env` extension
+    let env_file = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(".env"));
+    match language {
+        _ if env_file => redact::Assignments::All,
+        "dotenv" => redact::Assignments::All,
+        "ini" | "yaml" | "toml" => redact::Assignments::SecretKeys,
+        _ => redact::Assignments::None,
+    }
+}
+
+/// Prefixes every line with its right-aligned line number.
+fn number_lines(content: &str) -> String {
+    let width = content.lines().count().to_string().len();
+    let mut numbered = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| match line {
+            "" => format!("{:>width$} |", i + 1),
+            _ => format!("{:>width$} | {}", i + 1, line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if content.ends_with('\n') {
+        numbered.push('\n');
+    }
+    numbered
+}
+
+fn write_markdown(
+    out: &mut dyn Write,
+    config: &Config,
+    sections: &[SectionView],
+) -> io::Result<()> {
+    let headings = sections.len() > 1;
+    let anchors = if config.toc {
+        file_anchors(config, sections)
+    } else {
+        vec![]
+    };
+    let shown = ShownPaths::new(config, sections);
+    let mut after_sections = false;
+    for (i, section) in sections.iter().enumerate() {
+        if headings {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            writeln!(
+                out,
+                "## Directory: {}\n",
+                strip_shown_prefix(config, section.directory.display().to_string())
+            )?;
+        }
+        let mut after_section = false;
+        if shows_tree(config) {
+            write_markdown_header(out, &config.headers.tree, false)?;
+            print_tree(
+                out,
+                &tree_root(config, section.directory),
+                section.tree,
+                config.color,
+            )?;
+            after_section = true;
+        }
+        if !config.tree_only {
+            write_markdown_header(out, &config.headers.files, after_section)?;
+            let anchors = anchors.get(i).map(Vec::as_slice).unwrap_or_default();
+            if !anchors.is_empty() {
+                for (file, anchor) in section.files.iter().zip(anchors) {
+                    let path = shown.get(section.directory, &file.path);
+                    writeln!(out, "- [{}](#{})", path, anchor)?;
+                }
+                writeln!(out)?;
+            }
+            for (j, file) in section.files.iter().enumerate() {
+                if let Some(anchor) = anchors.get(j) {
+                    writeln!(out, "<a id=\"{}\"></a>", anchor)?;
+                }
+                write_markdown_file(out, &shown.get(section.directory, &file.path), &file)?;
+            }
+            after_section = true;
+        }
+        after_sections = after_section;
+    }
+
+    if let Some(prompt) = &config.prompt {
+        write_markdown_header(out, &config.headers.prompt, after_sections)?;
+        writeln!(out, "{}", prompt)?;
+    }
+
+    Ok(())
+}
+
+/// Link targets for each file of each section, made from their relative
+/// paths and numbered when two paths give the same one.
+fn file_anchors(config: &Config, sections: &[SectionView]) -> Vec<Vec<String>> {
+    let mut used = HashSet::new();
+    sections
+        .iter()
+        .map(|section| {
+            section
+                .files
+                .iter()
+                .map(|file| {
+                    let base = display_base(config, section.directory);
+                    let slug: String = relative_path(base, &file.path)
+                        .chars()
+                        .map(|c| {
+                            if c.is_alphanumeric() {
+                                c.to_ascii_lowercase()
+                            } else {
+                                '-'
+                            }
+                        })
+                        .collect();
+                    let base = format!("file-{}", slug);
+                    let mut anchor = base.clone();
+                    for n in 1.. {
+                        if used.insert(anchor.clone()) {
+                            break;
+                        }
+                        anchor = format!("{}-{}", base, n);
+                    }
+                    anchor
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes a heading, separated from the previous section if there is one.
+/// An empty heading only writes the separating line.
+fn write_markdown_header(out: &mut dyn Write, header: &str, after_section: bool) -> io::Result<()> {
+    match (header, after_section) {
+        ("", false) => Ok(()),
+        ("", true) => writeln!(out),
+        (header, false) => writeln!(out, "{}", header),
+        (header, true) => writeln!(out, "\n\n{}", header),
+    }
+}
+
+/// Shown in place of the contents of a file that could not be read, the
+/// error itself going to stderr so the document stays well formed.
+const READ_ERROR_PLACEHOLDER: &str = "// <error reading file>";
+
+/// Writes a file of a markdown document, labelled with its `shown` path.
+fn write_markdown_file(out: &mut dyn Write, shown: &str, file: &FileEntry) -> io::Result<()> {
+    let content = file.content.as_deref().unwrap_or(READ_ERROR_PLACEHOLDER);
+    let fence = code_fence(content);
+    writeln!(out, "- {}:", file_label(shown, file))?;
+    write_file_details(out, file)?;
+    writeln!(out, "{}{}", fence, file.language)?;
+    writeln!(out, "{}", content)?;
+    writeln!(out, "{}", fence)?;
+    writeln!(out)
+}
+
+/// `path` relative to the processed `directory`, with `/` separators on every
+/// platform. A file processed on its own is shown by its name.
+fn relative_path(directory: &Path, path: &Path) -> String {
+    let relative = match path.strip_prefix(directory) {
+        Ok(relative) if relative.as_os_str().is_empty() => path.file_name().map(PathBuf::from),
+        Ok(relative) => Some(relative.to_path_buf()),
+        // Such as a `--relative-to` directory above a relative `directory`
+        Err(_) => path_between(directory, path),
+    };
+    match relative {
+        Some(relative) => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        None => path.display().to_string(),
+    }
+}
+
+/// The directory that displayed paths are relative to: `relative_to`, or else
+/// the processed `directory`.
+fn display_base<'a>(config: &'a Config, directory: &'a Path) -> &'a Path {
+    config.relative_to.as_deref().unwrap_or(directory)
+}
+
+/// The path shown at the top of the tree of `directory`.
+fn tree_root(config: &Config, directory: &Path) -> PathBuf {
+    let root = match &config.relative_to {
+        None => directory.to_path_buf(),
+        Some(base) => match path_between(base, directory) {
+            Some(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+            Some(relative) => relative,
+            None => directory.to_path_buf(),
+        },
+    };
+    match config.strip_prefix {
+        Some(_) => PathBuf::from(strip_shown_prefix(config, root.display().to_string())),
+        None => root,
+    }
+}
+
+/// The paths shown for the files of a document, see [`shown_path`]. With
+/// [`Config::flatten`] they are just the file names instead, numbered from
+/// the second file sharing one, as in `mod.rs (2)`.
+struct ShownPaths<'a> {
+    config: &'a Config,
+    flat: HashMap<&'a Path, String>,
+}
+
+impl<'a> ShownPaths<'a> {
+    fn new(config: &'a Config, sections: &[SectionView<'a>]) -> Self {
+        let mut flat = HashMap::new();
+        if config.flatten {
+            let mut seen: HashMap<String, usize> = HashMap::new();
+            for path in sections.iter().flat_map(|section| section.files.paths()) {
+                let name = flat_name(path);
+                let count = seen.entry(name.clone()).or_default();
+                *count += 1;
+                let shown = match *count {
+                    1 => name,
+                    n => format!("{} ({})", name, n),
+                };
+                flat.insert(path, shown);
+            }
+        }
+        ShownPaths { config, flat }
+    }
+
+    fn get(&self, directory: &Path, path: &Path) -> String {
+        match self.flat.get(path) {
+            Some(shown) => shown.clone(),
+            None => shown_path(self.config, display_base(self.config, directory), path),
+        }
+    }
+}
+
+fn flat_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// `path`, a file or directory found under `directory`, as the JSON output
+/// and the reports of the command line tool show it: like the file labels
+/// of a document with [`Config::relative_to`] or [`Config::strip_prefix`]
+/// set, else as it was walked.
+pub fn display_path(config: &Config, directory: &Path, path: &Path) -> String {
+    match rewrites_paths(config) {
+        true => shown_path(config, display_base(config, directory), path),
+        false => path.display().to_string(),
+    }
+}
+
+/// The path of a file of `section` in a manifest or an archive of all
+/// `sections`: as shown in the document, under a folder named for its
+/// directory when there are several that paths are each relative to.
+pub(crate) fn entry_path(
+    config: &Config,
+    sections: &[Section],
+    section: &Section,
+    path: &Path,
+) -> String {
+    let shown = shown_path(config, display_base(config, &section.directory), path);
+    match sections.len() > 1 && config.relative_to.is_none() {
+        true => format!("{}/{}", entry_name(&section.directory), shown),
+        false => shown,
+    }
+}
+
+fn rewrites_paths(config: &Config) -> bool {
+    config.relative_to.is_some()
+        || config
+            .strip_prefix
+            .as_deref()
+            .is_some_and(|p| !p.is_empty())
+}
+
+/// `path` as shown in a document: relative to `directory`, then without
+/// [`Config::strip_prefix`].
+fn shown_path(config: &Config, directory: &Path, path: &Path) -> String {
+    strip_shown_prefix(config, relative_path(directory, path))
+}
+
+/// Removes [`Config::strip_prefix`] from the start of a shown path, after
+/// turning `\` into `/` in both, along with the `/` or `./` left behind. A
+/// path that was only the prefix becomes `.`, and other paths are kept.
+fn strip_shown_prefix(config: &Config, shown: String) -> String {
+    let Some(prefix) = config
+        .strip_prefix
+        .as_deref()
+        .filter(|prefix| !prefix.is_empty())
+    else {
+        return shown;
+    };
+    let normalized = shown.replace('\\', "/");
+    let Some(rest) = normalized.strip_prefix(&prefix.replace('\\', "/")) else {
+        return shown;
+    };
+    let rest = rest.trim_start_matches('/');
+    match rest.strip_prefix("./").unwrap_or(rest) {
+        "" => ".".to_string(),
+        rest => rest.to_string(),
+    }
+}
+
+/// The path leading from `base` to `path`, going up with `..` where needed,
+/// once both are resolved. `path` itself is not resolved, so a symlink keeps
+/// its own name.
+fn path_between(base: &Path, path: &Path) -> Option<PathBuf> {
+    let base = fs::canonicalize(base).ok()?;
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            fs::canonicalize(parent).ok()?.join(name)
+        }
+        _ => fs::canonicalize(path).ok()?,
+    };
+    let common = base
+        .components()
+        .zip(path.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return None;
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..base.components().count() {
+        relative.push("..");
+    }
+    relative.extend(path.components().skip(common));
+    Some(relative)
+}
+
+/// The shown path of a file, labelled with its type if it is base64-encoded.
+fn file_label(shown: &str, file: &FileEntry) -> String {
+    match &file.mime {
+        Some(mime) => format!("{} ({}, base64)", shown, mime),
+        None if !file.lines.is_empty() => {
+            format!("{} (lines {})", shown, format_ranges(&file.lines, ", "))
+        }
+        None => shown.to_string(),
+    }
+}
+
+fn format_ranges(ranges: &[LineRange], separator: &str) -> String {
+    let ranges: Vec<String> = ranges.iter().map(LineRange::to_string).collect();
+    ranges.join(separator)
+}
+
+/// The lines above the contents of a file: its metadata and line count, when
+/// collected.
+fn write_file_details(out: &mut dyn Write, file: &FileEntry) -> io::Result<()> {
+    if let Some(metadata) = &file.metadata {
+        write_metadata(out, metadata)?;
+    }
+    match file.line_count {
+        Some(1) => writeln!(out, "// 1 line"),
+        Some(count) => writeln!(out, "// {} lines", count),
+        None => Ok(()),
+    }
+}
+
+fn write_metadata(out: &mut dyn Write, metadata: &FileMetadata) -> io::Result<()> {
+    write!(out, "size: {}", format_size(metadata.size))?;
+    if let Some(modified) = &metadata.modified {
+        write!(out, ", modified: {}", modified)?;
+    }
+    writeln!(out)
+}
+
+fn shows_tree(config: &Config) -> bool {
+    config.tree_only || config.show_tree.unwrap_or(config.format != Format::Plain)
+}
+
+fn write_plain(out: &mut dyn Write, config: &Config, sections: &[SectionView]) -> io::Result<()> {
+    let shown = ShownPaths::new(config, sections);
+    for section in sections {
+        if shows_tree(config) {
+            print_tree(
+                out,
+                &tree_root(config, section.directory),
+                section.tree,
+                config.color,
+            )?;
+            writeln!(out)?;
+        }
+        for file in section.files.iter() {
+            write_plain_file(out, &shown.get(section.directory, &file.path), &file)?;
+        }
+    }
+
+    if let Some(prompt) = &config.prompt {
+        writeln!(out)?;
+        writeln!(out, "{}", prompt)?;
+    }
+
+    Ok(())
+}
+
+fn write_plain_file(out: &mut dyn Write, shown: &str, file: &FileEntry) -> io::Result<()> {
+    writeln!(out, "===== {} =====", file_label(shown, file))?;
+    write_file_details(out, file)?;
+    let content = file.content.as_deref().unwrap_or(READ_ERROR_PLACEHOLDER);
+    write!(out, "{}", content)?;
+    if !content.is_empty() && !content.ends_with('\n') {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_xml(out: &mut dyn Write, config: &Config, sections: &[SectionView]) -> io::Result<()> {
+    let shown = ShownPaths::new(config, sections);
+    writeln!(out, "<documents>")?;
+    for section in sections {
+        if shows_tree(config) {
+            match sections.len() {
+                1 => writeln!(out, "<tree>")?,
+                _ => writeln!(
+                    out,
+                    "<tree directory=\"{}\">",
+                    xml_escape(&strip_shown_prefix(
+                        config,
+                        section.directory.to_string_lossy().into_owned()
+                    ))
+                )?,
+            }
+            let root = tree_root(config, section.directory);
+            let tree = render_to_string(|out| print_tree(out, &root, section.tree, false));
+            write!(out, "{}", xml_escape(&tree))?;
+            writeln!(out, "</tree>")?;
+        }
+        for file in section.files.iter() {
+            write_xml_file(out, &shown.get(section.directory, &file.path), &file)?;
+        }
+    }
+    if let Some(prompt) = &config.prompt {
+        writeln!(out, "<prompt>\n{}\n</prompt>", xml_escape(prompt))?;
+    }
+    writeln!(out, "</documents>")
+}
+
+fn write_xml_file(out: &mut dyn Write, shown: &str, file: &FileEntry) -> io::Result<()> {
+    write!(out, "<file path=\"{}\"", xml_escape(shown))?;
+    if !file.language.is_empty() {
+        write!(out, " language=\"{}\"", xml_escape(&file.language))?;
+    }
+    if let Some(mime) = &file.mime {
+        write!(out, " mime=\"{}\"", xml_escape(mime))?;
+    }
+    if !file.lines.is_empty() {
+        write!(out, " lines=\"{}\"", format_ranges(&file.lines, ","))?;
+    }
+    if let Some(count) = file.line_count {
+        write!(out, " line-count=\"{}\"", count)?;
+    }
+    if let Some(metadata) = &file.metadata {
+        write!(out, " size=\"{}\"", metadata.size)?;
+        if let Some(modified) = &metadata.modified {
+            write!(out, " modified=\"{}\"", modified)?;
+        }
+    }
+    match (&file.content, &file.error) {
+        (Some(content), _) => {
+            writeln!(out, ">")?;
+            write!(out, "{}", xml_escape(content))?;
+            if !content.is_empty() && !content.ends_with('\n') {
+                writeln!(out)?;
+            }
+            writeln!(out, "</file>")
+        }
+        (None, error) => writeln!(
+            out,
+            " error=\"{}\" />",
+            xml_escape(error.as_deref().unwrap_or_default())
+        ),
+    }
+}
+
+/// Escapes text for XML content and double-quoted attributes.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns a backtick fence longer than any run of backticks in `content`,
+/// so files that contain fences themselves nest as valid CommonMark.
+fn code_fence(content: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in content.chars() {
+        if c == '`' {
+            run += 1;
+            longest = longest.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    "`".repeat((longest + 1).max(3))
+}
+
+fn write_json(out: &mut dyn Write, config: &Config, sections: &[SectionView]) -> io::Result<()> {
+    let prompt = config.prompt.as_deref();
+    match sections {
+        [section] => {
+            let document = JsonDocument {
+                tree: shows_tree(config).then_some(section.tree),
+                files: JsonFiles {
+                    config,
+                    directory: section.directory,
+                    files: section.files,
+                },
+                prompt,
+            };
+            serde_json::to_writer_pretty(&mut *out, &document)?;
+        }
+        _ => {
+            let sections = sections
+                .iter()
+                .map(|section| JsonSection {
+                    directory: strip_shown_prefix(config, section.directory.display().to_string()),
+                    tree: shows_tree(config).then_some(section.tree),
+                    files: JsonFiles {
+                        config,
+                        directory: section.directory,
+                        files: section.files,
+                    },
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut *out, &JsonSections { sections, prompt })?
+        }
+    }
+    writeln!(out)
+}
+
+/// Formats a byte count for humans, e.g. `512B`, `4.2KB`, `1MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    let rounded = (size * 10.0).round() / 10.0;
+    if rounded.fract() == 0.0 {
+        format!("{}{}", rounded, UNITS[unit])
+    } else {
+        format!("{:.1}{}", rounded, UNITS[unit])
+    }
+}
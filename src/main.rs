@@ -1,30 +1,45 @@
-use clap::{Arg, Command};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, Command};
+use dialoguer::MultiSelect;
 use glob::Pattern;
-use mime_guess::MimeGuess;
+use indicatif::{ProgressBar, ProgressStyle};
+use promptify::archive::write_archive;
+use promptify::imports::follow_imports;
+use promptify::manifest::write_manifest;
+use promptify::tokens::{
+    apply_token_budget, input_price, split_by_tokens, TokenCounter, DEFAULT_TOKEN_MODEL,
+};
+use promptify::watch::{wait_for_change, written_pattern, Snapshot};
+use promptify::{
+    apply_size_limit, build_section, build_tree, build_tree_checked, display_path, expand_braces,
+    format_size, parse_file_spec, parse_time, read_files, render_file, render_parts,
+    render_sections, render_template, render_tree, select_files, select_files_with_excluded,
+    split_patterns, text_section, Config, Encoding, Excluded, ExclusionReason, FileEntry, Format,
+    Headers, LineRange, Order, Section, Transform, Verbosity,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::{self, Read};
-use std::path::Path;
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
 
-#[derive(Clone, Debug)]
-struct TreeItem {
-    name: String,
-    children: Vec<TreeItem>,
-}
+/// Config file picked up from the current directory.
+const CONFIG_FILE: &str = "promptify.toml";
 
-impl TreeItem {
-    fn new(name: &str) -> Self {
-        TreeItem {
-            name: name.to_string(),
-            children: vec![],
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
         }
     }
-
-    fn add_child(&mut self, child: TreeItem) {
-        self.children.push(child);
-    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn run() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("file-lister")
         .version("0.1.0")
         .author("Moonstripe <moonstripe@protonmail.com>")
@@ -33,8 +48,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Arg::new("directory")
                 .short('d')
                 .long("directory")
-                .help("Directory to process")
-                .required(true),
+                .help("Directories to process, each rendered in its own section")
+                .num_args(1..)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("repo")
+                .long("repo")
+                .value_name("URL")
+                .help("Shallow-clone a git repository to a temporary directory and process it")
+                .conflicts_with("directory"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print fatal errors, no warnings about skipped files")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("verbose"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Also explain why entries are left out")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read default options from a TOML file (default: ./promptify.toml if it exists)"),
         )
         .arg(
             Arg::new("prompt")
@@ -42,6 +86,42 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .long("prompt")
                 .help("Enable prompts for each file"),
         )
+        .arg(
+            Arg::new("prompt-file")
+                .long("prompt-file")
+                .value_name("PATH")
+                .help("Read the prompt from a file")
+                .conflicts_with("prompt"),
+        )
+        .arg(
+            Arg::new("stdin-content")
+                .long("stdin-content")
+                .help("Format the text piped to stdin as a single file instead of walking a directory")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "directory",
+                    "files",
+                    "git-diff",
+                    "follow-imports",
+                    "repo",
+                    "interactive",
+                    "dry-run",
+                    "archive",
+                ]),
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_name("LANGUAGE")
+                .help("Fence language of the --stdin-content text, e.g. rust")
+                .requires("stdin-content"),
+        )
+        .arg(
+            Arg::new("expand-env")
+                .long("expand-env")
+                .help("Replace $VAR and ${VAR} in the prompt with environment variables, failing on unset ones")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("exclude")
                 .short('e')
@@ -49,224 +129,1580 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Comma-separated list of directories/patterns to exclude (supports glob patterns)")
                 .value_parser(clap::value_parser!(String)),
         )
+        .arg(
+            Arg::new("exclude-from")
+                .long("exclude-from")
+                .value_name("FILE")
+                .help("Also exclude the glob patterns listed in FILE, one per line, with # comments (repeatable)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("include")
+                .short('i')
+                .long("include")
+                .help("Comma-separated list of file patterns to include (supports glob patterns); excludes take precedence")
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(
+            Arg::new("ext")
+                .long("ext")
+                .value_name("EXTENSIONS")
+                .help("Only include files with these comma-separated extensions, e.g. rs,toml (short for --include '*.rs,*.toml')"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("Write the formatted output to a file instead of stdout"),
+        )
+        .arg(
+            Arg::new("tee")
+                .long("tee")
+                .help("Also print the output to stdout when writing it to a file with --output")
+                .action(clap::ArgAction::SetTrue)
+                .requires("output")
+                .conflicts_with_all(["split", "archive"]),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .help("Keep running and write the output again whenever a file changes")
+                .action(clap::ArgAction::SetTrue)
+                .requires("output")
+                .conflicts_with_all([
+                    "stdin-content",
+                    "repo",
+                    "dry-run",
+                    "archive",
+                    "split",
+                    "clipboard",
+                ]),
+        )
+        .arg(
+            Arg::new("gitignore")
+                .long("gitignore")
+                .help("Also exclude files matched by .gitignore rules")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-file")
+                .long("ignore-file")
+                .value_name("PATH")
+                .help("Also exclude files matched by this gitignore-style file, e.g. .dockerignore (repeatable)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("cache")
+                .long("cache")
+                .help("Reuse the contents of files unchanged since an earlier run with --cache")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .help("Keep the --cache in this directory (implies --cache)"),
+        )
+        .arg(
+            Arg::new("max-file-size")
+                .long("max-file-size")
+                .help("Omit the contents of files larger than this size (e.g. 512, 10K, 2M)")
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("max-files")
+                .long("max-files")
+                .value_name("N")
+                .help("Fail before reading anything when more than N files would be included")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .help("Read at most N files at once [default: one per CPU]")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("min-size")
+                .long("min-size")
+                .value_name("SIZE")
+                .help("Leave out files smaller than this size, e.g. 1 or 2K")
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("max-size")
+                .long("max-size")
+                .value_name("SIZE")
+                .help("Leave out files larger than this size, e.g. 100K")
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("max-total-size")
+                .long("max-total-size")
+                .value_name("SIZE")
+                .help("Stop including file contents once the output reaches this size, e.g. 2M")
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("truncate")
+                .long("truncate")
+                .value_name("N")
+                .help("Show only the first and last N lines of files longer than 2N lines")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .help("Output format")
+                .value_parser(
+                    PossibleValuesParser::new(["markdown", "json", "plain", "xml", "html"])
+                        .try_map(|format| format.parse::<Format>()),
+                )
+                .default_value("markdown"),
+        )
+        .arg(
+            Arg::new("order")
+                .long("order")
+                .help("The order of the files in the listing: tree, alpha, size (largest first), mtime (newest first) or type")
+                .value_parser(
+                    PossibleValuesParser::new(["tree", "alpha", "size", "mtime", "type"])
+                        .try_map(|order| order.parse::<Order>()),
+                )
+                .default_value("tree"),
+        )
+        .arg(
+            Arg::new("encoding")
+                .long("encoding")
+                .help("How to handle files that are not valid UTF-8")
+                .value_parser(
+                    PossibleValuesParser::new(["lossy", "skip", "strict"])
+                        .try_map(|encoding| encoding.parse::<Encoding>()),
+                )
+                .default_value("lossy"),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("FILE")
+                .help("Render into a template with {{tree}}, {{files}} and {{prompt}} placeholders"),
+        )
+        .arg(
+            Arg::new("prepend")
+                .long("prepend")
+                .value_name("TEXT|@FILE")
+                .help("Put this text, or the contents of FILE, at the very start of the output")
+                .conflicts_with_all(["split", "archive"]),
+        )
+        .arg(
+            Arg::new("append")
+                .long("append")
+                .value_name("TEXT|@FILE")
+                .help("Put this text, or the contents of FILE, at the very end of the output")
+                .conflicts_with_all(["split", "archive"]),
+        )
+        .arg(
+            Arg::new("scope")
+                .long("scope")
+                .value_name("PREFIX")
+                .help("Only include files under this path, still showing the tree from the directory (repeatable)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("relative-to")
+                .long("relative-to")
+                .value_name("DIR")
+                .help("Show paths relative to this directory, e.g. the repository root"),
+        )
+        .arg(
+            Arg::new("flatten")
+                .long("flatten")
+                .help("Label the listed files by their names alone, numbering repeated names")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strip-prefix")
+                .long("strip-prefix")
+                .value_name("TEXT")
+                .help("Remove this text from the start of the paths shown, e.g. /home/me/projects/"),
+        )
+        .arg(
+            Arg::new("tree-header")
+                .long("tree-header")
+                .value_name("TEXT")
+                .help("Heading above the file tree in markdown output (default: \"### File Tree:\")"),
+        )
+        .arg(
+            Arg::new("files-header")
+                .long("files-header")
+                .value_name("TEXT")
+                .help("Heading above the files in markdown output (default: \"### Files:\")"),
+        )
+        .arg(
+            Arg::new("prompt-header")
+                .long("prompt-header")
+                .value_name("TEXT")
+                .help("Heading above the prompt in markdown output (default: \"### Prompt:\")"),
+        )
+        .arg(
+            Arg::new("no-headers")
+                .long("no-headers")
+                .help("Leave out the section headings of markdown output")
+                .conflicts_with_all(["tree-header", "files-header", "prompt-header"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tree")
+                .long("tree")
+                .help("Include the file tree in plain output")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tree-sizes")
+                .long("tree-sizes")
+                .help("Show file sizes and directory totals in the tree")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-tree")
+                .long("no-tree")
+                .help("Leave out the file tree")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["tree", "tree-only"]),
+        )
+        .arg(
+            Arg::new("tree-only")
+                .long("tree-only")
+                .help("Print just the file tree (and the prompt), without any file contents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("count-tokens")
+                .long("count-tokens")
+                .value_name("MODEL")
+                .help("Print a token count summary for the given model (e.g. gpt-4, cl100k_base) to stderr"),
+        )
+        .arg(
+            Arg::new("cost")
+                .long("cost")
+                .value_name("MODEL")
+                .help("Print the estimated input cost for the given model (e.g. gpt-4o) to stderr"),
+        )
+        .arg(
+            Arg::new("price")
+                .long("price")
+                .value_name("USD")
+                .help("Input price per million tokens for --cost, overriding the built-in one")
+                .requires("cost")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("max-tokens")
+                .long("max-tokens")
+                .help("Stop including files once the output would exceed this many tokens")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("FILE")
+                .help("Also write a JSON manifest of the included files with their sizes and SHA-256 hashes"),
+        )
+        .arg(
+            Arg::new("archive")
+                .long("archive")
+                .value_name("FILE")
+                .help("Write the included files and a promptify.md with the tree and prompt to a zip archive")
+                .conflicts_with_all(["split", "template", "clipboard", "output"]),
+        )
+        .arg(
+            Arg::new("split")
+                .long("split")
+                .value_name("MAX_TOKENS")
+                .help("Write the output as part-001.md, part-002.md, ... of at most this many tokens each")
+                .value_parser(clap::value_parser!(usize))
+                .conflicts_with_all(["max-tokens", "template", "clipboard"]),
+        )
+        .arg(
+            Arg::new("files")
+                .long("files")
+                .value_name("FILE")
+                .help("Render only the newline-separated paths listed in FILE (or stdin for -), relative to the directory"),
+        )
+        .arg(
+            Arg::new("modified-since")
+                .long("modified-since")
+                .value_name("TIME")
+                .help("Only include files modified after a date (2024-01-01) or within a duration (7d, 12h)")
+                .value_parser(parse_time),
+        )
+        .arg(
+            Arg::new("follow-imports")
+                .long("follow-imports")
+                .value_name("FILE")
+                .help("Only render this entry file and the local files it imports, transitively (repeatable)")
+                .action(clap::ArgAction::Append)
+                .conflicts_with_all(["files", "git-diff"]),
+        )
+        .arg(
+            Arg::new("git-diff")
+                .long("git-diff")
+                .value_name("REF")
+                .help("Only render files that changed relative to a git ref, e.g. main")
+                .conflicts_with("files"),
+        )
+        .arg(
+            Arg::new("line-numbers")
+                .short('n')
+                .long("line-numbers")
+                .help("Prefix each line of file contents with its line number")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("line-numbers-min")
+                .long("line-numbers-min")
+                .value_name("LINES")
+                .help("Only number files with at least this many lines (implies --line-numbers)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("clipboard")
+                .short('c')
+                .long("clipboard")
+                .help("Copy the formatted output to the system clipboard instead of printing it")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("depth")
+                .long("depth")
+                .help("Only descend this many directory levels below the directory (which is depth 0)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .help("Color the tree when printing to a terminal, unless NO_COLOR is set")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("toc")
+                .long("toc")
+                .help("List the included files as links to their contents (markdown)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("line-counts")
+                .long("line-counts")
+                .help("Show each file's line count above its contents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("metadata")
+                .long("metadata")
+                .help("Show each file's size and last-modified date above its contents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .help("Print a summary of the included files to stderr")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show-excluded-summary")
+                .long("show-excluded-summary")
+                .help("Print how many files and bytes were excluded, and why, to stderr")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-recursive")
+                .long("no-recursive")
+                .help("Only read the files directly inside the directory (same as --depth 0)")
+                .conflicts_with("depth")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .help("Show progress on stderr while scanning and reading files")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("interactive")
+                .long("interactive")
+                .help("Pick the files to include from a checkbox list")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Only print the tree and the paths of the files that would be included")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lang-map")
+                .long("lang-map")
+                .value_name("EXT=LANG,...")
+                .help("Comma-separated fence languages for file extensions, e.g. kt=kotlin,tpl=html"),
+        )
+        .arg(
+            Arg::new("keep-empty-dirs")
+                .long("keep-empty-dirs")
+                .help("Show directories in the tree even when nothing in them is listed")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("only-lang")
+                .long("only-lang")
+                .value_name("LANGS")
+                .help("Only include files detected as one of these comma-separated languages (e.g. rust,toml)"),
+        )
+        .arg(
+            Arg::new("skip-lang")
+                .long("skip-lang")
+                .value_name("LANGS")
+                .help("Leave out files detected as one of these comma-separated languages"),
+        )
+        .arg(
+            Arg::new("include-binary")
+                .long("include-binary")
+                .value_name("EXTS")
+                .help("Include binary files with these comma-separated extensions base64-encoded (e.g. png,pdf)"),
+        )
+        .arg(
+            Arg::new("hidden")
+                .long("hidden")
+                .help("Include hidden files and directories (names starting with '.')")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include-lockfiles")
+                .long("include-lockfiles")
+                .help("List the contents of lockfiles such as Cargo.lock and package-lock.json")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("follow-symlinks")
+                .long("follow-symlinks")
+                .help("Follow symlinked files and directories instead of skipping them")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("redact")
+                .long("redact")
+                .help("Mask values that look like secrets, such as .env values and API keys")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("notebook-outputs")
+                .long("notebook-outputs")
+                .help("Include cell outputs when converting Jupyter notebooks")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("csv-rows")
+                .long("csv-rows")
+                .value_name("N")
+                .help("Keep only the header and first N rows of CSV and TSV files [default: 10]")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("minify")
+                .long("minify")
+                .help("Compact JSON and YAML files to save tokens, leaving files that do not parse as they are")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("skip-minified")
+                .long("skip-minified")
+                .help("Leave out files whose lines are hundreds of characters long on average")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-content-on-error")
+                .long("no-content-on-error")
+                .help("Leave out files that cannot be read instead of showing a placeholder")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedupe")
+                .long("dedupe")
+                .help("Show files identical to an earlier file as a reference to it")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trim")
+                .long("trim")
+                .help("Strip trailing whitespace and surplus blank lines from file contents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strip-comments")
+                .long("strip-comments")
+                .help("Remove comments from source files in languages with a known comment syntax")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("transform")
+                .long("transform")
+                .value_name("PATTERN=COMMAND")
+                .help("Pipe the contents of files matching PATTERN through a shell COMMAND, e.g. '*.sql=sqlformat -' (repeatable)")
+                .value_parser(|transform: &str| transform.parse::<Transform>())
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("summary-only")
+                .long("summary-only")
+                .help("Show only the declarations of Rust and Python files, such as function signatures, structs and classes")
+                .action(clap::ArgAction::SetTrue),
+        )
         .get_matches();
 
-    let directory = matches
-        .get_one::<String>("directory")
-        .expect("Directory is required");
-
-    // Parse exclude patterns
-    let exclude_patterns: Vec<Pattern> = matches
-        .get_one::<String>("exclude")
-        .map(|e| {
-            e.split(',')
-                .filter_map(|pattern| {
-                    Pattern::new(pattern.trim())
-                        .map_err(|err| {
-                            eprintln!("Warning: Invalid glob pattern '{}': {}", pattern, err);
-                            err
-                        })
-                        .ok()
-                })
-                .collect()
+    // Options from the config file, overridden by the ones given on the command line
+    let mut config = load_config(matches.get_one::<String>("config"))?;
+    // The clone is removed when dropped, so also when returning early with an error
+    let clone = match matches.get_one::<String>("repo") {
+        Some(url) => Some(clone_repo(url)?),
+        None => None,
+    };
+    let directories: Vec<PathBuf> = match matches.get_many::<String>("directory") {
+        Some(directories) => directories.map(PathBuf::from).collect(),
+        None if clone.is_some() => clone
+            .iter()
+            .map(|(_, directory)| directory.clone())
+            .collect(),
+        None if !config.directory.as_os_str().is_empty() => vec![config.directory.clone()],
+        None if matches.contains_id("files")
+            || matches.contains_id("git-diff")
+            || matches.get_flag("stdin-content")
+            || config.files.is_some() =>
+        {
+            vec![PathBuf::from(".")]
+        }
+        None => {
+            return Err(format!(
+                "No directory given, pass -d or set `directory` in {}",
+                CONFIG_FILE
+            )
+            .into())
+        }
+    };
+    config.directory = directories[0].clone();
+    // First, as it decides whether parsing the other options warns
+    if matches.get_flag("quiet") {
+        config.verbosity = Verbosity::Quiet;
+    } else if matches.get_flag("verbose") {
+        config.verbosity = Verbosity::Verbose;
+    }
+
+    if matches.contains_id("exclude") {
+        config.exclude_patterns =
+            parse_patterns(matches.get_one::<String>("exclude"), config.verbosity);
+    }
+    for path in matches
+        .get_many::<String>("exclude-from")
+        .into_iter()
+        .flatten()
+    {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read exclude file {}: {}", path, err))?;
+        config.exclude_patterns.extend(compile_patterns(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .flat_map(expand_braces),
+            config.verbosity,
+        ));
+    }
+    if matches.contains_id("include") {
+        config.include_patterns =
+            parse_patterns(matches.get_one::<String>("include"), config.verbosity);
+    }
+    if let Some(extensions) = matches.get_one::<String>("ext") {
+        config.include_patterns.extend(compile_patterns(
+            parse_list(extensions)
+                .into_iter()
+                .map(|ext| format!("*.{}", Pattern::escape(ext.trim_start_matches('.')))),
+            config.verbosity,
+        ));
+    }
+    config.gitignore |= matches.get_flag("gitignore");
+    if let Some(files) = matches.get_many::<String>("ignore-file") {
+        config.ignore_files.extend(files.map(PathBuf::from));
+    }
+    if let Some(scopes) = matches.get_many::<String>("scope") {
+        config.scopes.extend(scopes.map(PathBuf::from));
+    }
+    if let Some(base) = matches.get_one::<String>("relative-to") {
+        config.relative_to = Some(PathBuf::from(base));
+    }
+    config.flatten |= matches.get_flag("flatten");
+    if let Some(prefix) = matches.get_one::<String>("strip-prefix") {
+        config.strip_prefix = Some(prefix.clone());
+    }
+    if let Some(cache_dir) = matches.get_one::<String>("cache-dir") {
+        config.cache_dir = Some(PathBuf::from(cache_dir));
+    } else if matches.get_flag("cache") && config.cache_dir.is_none() {
+        config.cache_dir = default_cache_dir();
+    }
+    if let Some(&min_size) = matches.get_one::<u64>("min-size") {
+        config.min_size = Some(min_size);
+    }
+    if let Some(&max_size) = matches.get_one::<u64>("max-size") {
+        config.max_size = Some(max_size);
+    }
+    if let Some(&max_file_size) = matches.get_one::<u64>("max-file-size") {
+        config.max_file_size = Some(max_file_size);
+    }
+    if let Some(&max_files) = matches.get_one::<usize>("max-files") {
+        config.max_files = Some(max_files);
+    }
+    if let Some(&jobs) = matches.get_one::<usize>("jobs") {
+        config.jobs = Some(jobs);
+    }
+    if matches.value_source("format") == Some(ValueSource::CommandLine) {
+        config.format = *matches
+            .get_one::<Format>("format")
+            .expect("Format was given");
+    }
+    if matches.value_source("encoding") == Some(ValueSource::CommandLine) {
+        config.encoding = *matches
+            .get_one::<Encoding>("encoding")
+            .expect("Encoding was given");
+    }
+    if matches.value_source("order") == Some(ValueSource::CommandLine) {
+        config.order = *matches.get_one::<Order>("order").expect("Order was given");
+    }
+    if matches.get_flag("no-headers") {
+        config.headers = Headers::none();
+    }
+    if let Some(header) = matches.get_one::<String>("tree-header") {
+        config.headers.tree = header.clone();
+    }
+    if let Some(header) = matches.get_one::<String>("files-header") {
+        config.headers.files = header.clone();
+    }
+    if let Some(header) = matches.get_one::<String>("prompt-header") {
+        config.headers.prompt = header.clone();
+    }
+    if matches.get_flag("tree") {
+        config.show_tree = Some(true);
+    } else if matches.get_flag("no-tree") {
+        config.show_tree = Some(false);
+    }
+    config.tree_only |= matches.get_flag("tree-only");
+    config.tree_sizes |= matches.get_flag("tree-sizes");
+    if let Some(path) = matches.get_one::<String>("prompt-file") {
+        config.prompt = Some(
+            fs::read_to_string(path)?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        );
+    } else if let Some(prompt) = matches.get_one::<String>("prompt") {
+        config.prompt = Some(prompt.clone());
+    }
+    if matches.get_flag("expand-env") {
+        if let Some(prompt) = &config.prompt {
+            config.prompt = Some(expand_env(prompt)?);
+        }
+    }
+    config.languages.extend(parse_lang_map(
+        matches.get_one::<String>("lang-map"),
+        config.verbosity,
+    ));
+    if let Some(&since) = matches.get_one::<SystemTime>("modified-since") {
+        config.modified_since = Some(since);
+    }
+    if let Some(languages) = matches.get_one::<String>("only-lang") {
+        config.only_languages = parse_list(languages);
+    }
+    if let Some(languages) = matches.get_one::<String>("skip-lang") {
+        config.skip_languages = parse_list(languages);
+    }
+    if let Some(extensions) = matches.get_one::<String>("include-binary") {
+        config.include_binary = extensions
+            .split(',')
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+    }
+    config.hidden |= matches.get_flag("hidden");
+    config.show_excluded_summary |= matches.get_flag("show-excluded-summary");
+    config.include_lockfiles |= matches.get_flag("include-lockfiles");
+    config.keep_empty_dirs |= matches.get_flag("keep-empty-dirs");
+    config.follow_symlinks |= matches.get_flag("follow-symlinks");
+    config.redact |= matches.get_flag("redact");
+    config.notebook_outputs |= matches.get_flag("notebook-outputs");
+    config.dedupe |= matches.get_flag("dedupe");
+    config.skip_minified |= matches.get_flag("skip-minified");
+    config.no_content_on_error |= matches.get_flag("no-content-on-error");
+    config.minify |= matches.get_flag("minify");
+    if let Some(&rows) = matches.get_one::<usize>("csv-rows") {
+        config.csv_rows = Some(rows);
+    }
+    config.strip_comments |= matches.get_flag("strip-comments");
+    config.summary_only |= matches.get_flag("summary-only");
+    if let Some(transforms) = matches.get_many::<Transform>("transform") {
+        // Given on the command line, they come before those of the config file
+        let mut transforms: Vec<Transform> = transforms.cloned().collect();
+        transforms.append(&mut config.transforms);
+        config.transforms = transforms;
+    }
+    config.trim |= matches.get_flag("trim");
+    config.metadata |= matches.get_flag("metadata");
+    config.line_counts |= matches.get_flag("line-counts");
+    config.toc |= matches.get_flag("toc");
+    if let Some(&lines) = matches.get_one::<usize>("truncate") {
+        config.truncate_lines = Some(lines);
+    }
+    if matches.get_flag("no-recursive") {
+        config.max_depth = Some(0);
+    } else if let Some(&depth) = matches.get_one::<usize>("depth") {
+        config.max_depth = Some(depth);
+    }
+    if let Some(&min_lines) = matches.get_one::<usize>("line-numbers-min") {
+        config.line_numbers = Some(min_lines);
+    } else if matches.get_flag("line-numbers") {
+        config.line_numbers = Some(0);
+    }
+    if let Some(list) = matches.get_one::<String>("files") {
+        if directories.len() > 1 {
+            return Err("--files can only be used with a single directory".into());
+        }
+        read_file_list(list, &mut config)?;
+    }
+    if let Some(reference) = matches.get_one::<String>("git-diff") {
+        if directories.len() > 1 {
+            return Err("--git-diff can only be used with a single directory".into());
+        }
+        config.files = Some(git_changed_files(&config.directory, reference)?);
+    }
+
+    if let Some(entries) = matches.get_many::<String>("follow-imports") {
+        if directories.len() > 1 {
+            return Err("--follow-imports can only be used with a single directory".into());
+        }
+        let entries: Vec<PathBuf> = entries.map(PathBuf::from).collect();
+        config.files = Some(follow_imports(&config.directory, &entries)?);
+    }
+
+    for directory in &directories {
+        check_readable(directory)?;
+    }
+
+    let max_tokens = matches.get_one::<usize>("max-tokens").copied();
+    let counter = match matches.get_one::<String>("count-tokens") {
+        Some(model) => Some(token_counter(model, config.verbosity)?),
+        None if max_tokens.is_some() || matches.contains_id("split") => {
+            Some(token_counter(DEFAULT_TOKEN_MODEL, config.verbosity)?)
+        }
+        None => None,
+    };
+    if matches.contains_id("split") && config.format == Format::Json {
+        return Err("--split cannot be used with --format json".into());
+    }
+    let prepend = read_raw_text(matches.get_one::<String>("prepend"))?;
+    let append = read_raw_text(matches.get_one::<String>("append"))?;
+    if (prepend.is_some() || append.is_some())
+        && matches!(config.format, Format::Json | Format::Html)
+    {
+        return Err("--prepend and --append cannot be used with --format json or html".into());
+    }
+    // Escape codes only belong in text a person reads in the terminal, not
+    // in files, the clipboard or token counts
+    config.color = (config.color || matches.get_flag("color"))
+        && matches!(config.format, Format::Markdown | Format::Plain)
+        && io::stdout().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && counter.is_none()
+        && !matches.get_flag("clipboard")
+        && !["output", "split", "archive"]
+            .iter()
+            .any(|id| matches.contains_id(id));
+
+    if matches.get_flag("stdin-content") {
+        // There is nothing to show in a tree
+        config.show_tree = Some(false);
+    }
+    let mut configs: Vec<Config> = directories
+        .iter()
+        .map(|directory| Config {
+            directory: directory.clone(),
+            ..config.clone()
         })
-        .unwrap_or_default();
-    println!("### File Tree:");
-    // Print the directory tree
-    print_tree(directory.as_str(), &exclude_patterns)?;
-    println!("\n\n### Files:");
-    // Process the files
-    list_dir_recursive(Path::new(directory), &exclude_patterns)?;
+        .collect();
 
-    if let Some(prompt) = matches.get_one::<String>("prompt") {
-        println!("\n\n### Prompt:");
-        println!("{}", prompt);
+    if matches.get_flag("interactive") {
+        // The picker draws on stderr, so stdout can still be piped
+        if io::stdin().is_terminal() && io::stderr().is_terminal() {
+            for config in &mut configs {
+                pick_files(config)?;
+            }
+        } else if config.verbosity > Verbosity::Quiet {
+            eprintln!("Warning: --interactive needs a terminal, including every file");
+        }
     }
 
-    Ok(())
+    if matches.get_flag("dry-run") {
+        let mut out = open_output(matches.get_one::<String>("output"))?;
+        for (i, config) in configs.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            write_dry_run(&mut out, config)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+
+    if !matches.get_flag("watch") {
+        return write_output(
+            &matches,
+            &config,
+            &configs,
+            counter.as_ref(),
+            prepend.as_deref(),
+            append.as_deref(),
+        );
+    }
+    let output = matches
+        .get_one::<String>("output")
+        .expect("Required by --watch");
+    // What a run writes must not show up in it, nor count as a change, or
+    // each run would start the next. It is created up front so the patterns
+    // excluding it always match.
+    let mut written = vec![PathBuf::from(output)];
+    written.extend(matches.get_one::<String>("manifest").map(PathBuf::from));
+    for path in &written {
+        fs::File::create(path)
+            .map_err(|err| format!("Could not create {}: {}", path.display(), err))?;
+    }
+    if let Some(cache_dir) = &config.cache_dir {
+        fs::create_dir_all(cache_dir)
+            .map_err(|err| format!("Could not create {}: {}", cache_dir.display(), err))?;
+        written.push(cache_dir.clone());
+    }
+    for config in &mut configs {
+        for path in &written {
+            if let Some(pattern) = written_pattern(&config.directory, path) {
+                config.exclude_patterns.push(pattern);
+            }
+        }
+    }
+    if config.verbosity > Verbosity::Quiet {
+        eprintln!("Watching for changes, press Ctrl-C to stop");
+    }
+    let mut snapshot = Snapshot::take(&configs);
+    loop {
+        match write_output(
+            &matches,
+            &config,
+            &configs,
+            counter.as_ref(),
+            prepend.as_deref(),
+            append.as_deref(),
+        ) {
+            Ok(()) if config.verbosity > Verbosity::Quiet => eprintln!("Wrote {}", output),
+            Ok(()) => {}
+            // A file changing mid-run is no reason to stop watching
+            Err(err) => eprintln!("Error: {}", err),
+        }
+        snapshot = wait_for_change(&configs, snapshot, WATCH_INTERVAL);
+    }
 }
 
-fn is_plain_text_file(path: &Path) -> bool {
-    // List of file extensions we want to explicitly consider as plain text
-    const PLAIN_TEXT_EXTENSIONS: &[&str] = &[
-        // web development
-        "ts", "tsx", "js", "jsx", "json", "html", "htm", "css", "scss", "sass",
-        // template files
-        "twig", "ejs", "hbs", "vue", "svelte", // config files
-        "yml", "yaml", "toml", "ini", "env", // documentation
-        "md", "markdown", "txt", "rst", // other programming languages
-        "py", "rb", "php", "java", "go", "rs", "c", "cpp", "h", "hpp", "sh", "bash",
-    ];
-
-    if let Some(extension) = path.extension() {
-        if let Some(ext_str) = extension.to_str() {
-            // Check against our explicit list first
-            if PLAIN_TEXT_EXTENSIONS.contains(&ext_str.to_lowercase().as_str()) {
-                return true;
+/// How often the watched files are checked for changes by `--watch`.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Builds the sections of `configs`, applies the limits and writes the
+/// rendered output, or the manifest, archive or parts asked for.
+fn write_output(
+    matches: &ArgMatches,
+    config: &Config,
+    configs: &[Config],
+    counter: Option<&TokenCounter>,
+    prepend: Option<&str>,
+    append: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Build the tree and process the files of each directory
+    let mut sections = vec![];
+    if matches.get_flag("stdin-content") {
+        if io::stdin().is_terminal() {
+            return Err("--stdin-content formats piped text, but stdin is a terminal".into());
+        }
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        let language = matches.get_one::<String>("lang").map_or("", String::as_str);
+        sections.push(text_section(config, "stdin", language, content));
+    } else {
+        for config in configs {
+            sections.push(match matches.get_flag("progress") {
+                true => build_section_with_progress(config)?,
+                false => build_section(config)?,
+            });
+        }
+    }
+
+    // A pattern matching in any of the directories is fine
+    if config.verbosity > Verbosity::Quiet {
+        for pattern in &sections[0].unmatched_excludes {
+            if sections[1..]
+                .iter()
+                .all(|section| section.unmatched_excludes.contains(pattern))
+            {
+                eprintln!("Warning: Exclude pattern '{}' matched nothing", pattern);
             }
         }
     }
+    if config.show_excluded_summary {
+        report_excluded(config, &sections);
+    }
+    if let Some(&max_size) = matches.get_one::<u64>("max-total-size") {
+        let omitted = apply_size_limit(config, max_size, &mut sections);
+        if omitted > 0 && config.verbosity > Verbosity::Quiet {
+            eprintln!(
+                "Note: {} files omitted to stay within the {} total size limit",
+                omitted,
+                format_size(max_size)
+            );
+        }
+    }
+    if let Some(&max_tokens) = matches.get_one::<usize>("max-tokens") {
+        let counter = counter.expect("Created for --max-tokens");
+        let omitted = apply_token_budget(counter, max_tokens, config, &mut sections);
+        if omitted > 0 && config.verbosity > Verbosity::Quiet {
+            eprintln!(
+                "Note: {} files omitted to stay within the {} token budget",
+                omitted, max_tokens
+            );
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("manifest") {
+        let file = fs::File::create(path)
+            .map_err(|err| format!("Could not create manifest {}: {}", path, err))?;
+        write_manifest(config, &sections, BufWriter::new(file))?;
+    }
+    if let Some(path) = matches.get_one::<String>("archive") {
+        let file = fs::File::create(path)
+            .map_err(|err| format!("Could not create archive {}: {}", path, err))?;
+        let archived = write_archive(config, &sections, BufWriter::new(file))?;
+        if config.verbosity > Verbosity::Quiet {
+            eprintln!("Wrote {} ({} files)", path, archived);
+        }
+        return Ok(());
+    }
+    if let Some(&max_tokens) = matches.get_one::<usize>("split") {
+        let counter = counter.expect("Created for --split");
+        let parts = split_by_tokens(counter, max_tokens, config, sections);
+        write_parts(config, &parts, matches.get_one::<String>("output"), counter)?;
+        return Ok(());
+    }
 
-    // Fall back to mime_guess for other files
-    match MimeGuess::from_path(path).first() {
-        Some(mime_type) => {
-            mime_type.type_() == "text"
-                || (mime_type.type_() == "application" && (mime_type.subtype() == "json"))
+    let mut rendered = match matches.get_one::<String>("template") {
+        Some(path) => {
+            let template = fs::read_to_string(path)
+                .map_err(|err| format!("Could not read template {}: {}", path, err))?;
+            render_template(&template, config, &sections)
+                .map_err(|err| format!("Invalid template {}: {}", path, err))?
         }
-        None => false,
+        None => render_sections(config, &sections),
+    };
+    if let Some(prepend) = prepend {
+        rendered.insert_str(0, prepend);
+    }
+    if let Some(append) = append {
+        rendered.push_str(append);
+    }
+    // With the paths shown for them, which depend on their section
+    let files: Vec<(String, &FileEntry)> = sections
+        .iter()
+        .flat_map(|section| {
+            section
+                .files
+                .iter()
+                .map(|file| (display_path(config, &section.directory, &file.path), file))
+        })
+        .collect();
+    if matches.get_flag("stats") {
+        report_stats(&files);
+    }
+    if let Some(counter) = counter {
+        report_tokens(counter, &rendered, config, &files);
+    }
+    if let Some(model) = matches.get_one::<String>("cost") {
+        let price = matches
+            .get_one::<f64>("price")
+            .copied()
+            .or_else(|| input_price(model));
+        match price {
+            Some(price) => report_cost(&token_counter(model, config.verbosity)?, price, &rendered),
+            None if config.verbosity > Verbosity::Quiet => eprintln!(
+                "Warning: No known price for model '{}', pass --price to estimate the cost",
+                model
+            ),
+            None => {}
+        }
+    }
+
+    // The clipboard replaces stdout, but not an explicit output file
+    let copied = matches.get_flag("clipboard")
+        && match copy_to_clipboard(&rendered) {
+            Ok(()) => true,
+            Err(err) => {
+                if config.verbosity > Verbosity::Quiet {
+                    eprintln!(
+                        "Warning: Could not copy to the clipboard ({}), writing to stdout instead",
+                        err
+                    );
+                }
+                false
+            }
+        };
+    if !copied || matches.contains_id("output") {
+        let mut out = open_output(matches.get_one::<String>("output"))?;
+        out.write_all(rendered.as_bytes())?;
+        out.flush()?;
+    }
+    if matches.get_flag("tee") {
+        let mut out = io::stdout().lock();
+        out.write_all(rendered.as_bytes())?;
+        out.flush()?;
     }
+    Ok(())
 }
 
-fn read_file(path: &Path) -> io::Result<String> {
-    let mut content = String::new();
-    let mut file = fs::File::open(path)?;
-    file.read_to_string(&mut content)?;
-    Ok(content)
+/// Opens the requested output file, created fresh each run, or stdout.
+fn open_output(output: Option<&String>) -> io::Result<Box<dyn Write>> {
+    Ok(match output {
+        Some(output) => Box::new(BufWriter::new(fs::File::create(output)?)),
+        None => Box::new(io::stdout().lock()),
+    })
 }
 
-fn should_exclude(path: &Path, exclude_patterns: &[Pattern]) -> bool {
-    let path_str = path.to_string_lossy();
-    exclude_patterns
-        .iter()
-        .any(|pattern| pattern.matches(&path_str.replace("./", "")))
+/// Writes each part of a `--split` document to its own numbered file, named
+/// after `output` if given.
+fn write_parts(
+    config: &Config,
+    parts: &[Vec<Section>],
+    output: Option<&String>,
+    counter: &TokenCounter,
+) -> io::Result<()> {
+    let extension = match config.format {
+        Format::Markdown => "md",
+        Format::Json => "json",
+        Format::Plain => "txt",
+        Format::Xml => "xml",
+        Format::Html => "html",
+    };
+    let (stem, extension) = match output.map(Path::new) {
+        Some(output) => (
+            output.with_extension(""),
+            output
+                .extension()
+                .map_or(extension.into(), |ext| ext.to_string_lossy()),
+        ),
+        None => (PathBuf::from("part"), extension.into()),
+    };
+
+    for (i, rendered) in render_parts(config, parts).iter().enumerate() {
+        let mut path = stem.clone().into_os_string();
+        path.push(format!("-{:03}.{}", i + 1, extension));
+        fs::write(&path, rendered)?;
+        if config.verbosity > Verbosity::Quiet {
+            eprintln!(
+                "Wrote {} ({} tokens)",
+                Path::new(&path).display(),
+                counter.count(rendered)
+            );
+        }
+    }
+    Ok(())
 }
 
-fn build_tree(
-    path: &Path,
-    exclude_patterns: &[Pattern],
-) -> Result<TreeItem, Box<dyn std::error::Error>> {
-    let metadata = fs::metadata(path)?;
-    let mut root = TreeItem::new(
-        path.file_name()
-            .unwrap_or(path.as_os_str())
-            .to_str()
-            .unwrap(),
+/// Builds a section like [`build_section`], with a spinner while scanning and
+/// a progress bar while reading. Both are drawn on stderr, and only when it is
+/// a terminal.
+fn build_section_with_progress(config: &Config) -> io::Result<Section> {
+    let spinner =
+        ProgressBar::new_spinner().with_message(format!("Scanning {}", config.directory.display()));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let (tree, unmatched_excludes) = build_tree_checked(config)?;
+    let (paths, excluded) = select_files_with_excluded(config)?;
+    spinner.finish_and_clear();
+
+    let bar = ProgressBar::new(paths.len() as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} files {wide_msg}")
+            .expect("Valid progress template"),
     );
+    let files = read_files(config, paths, |path| {
+        bar.set_message(path.display().to_string());
+        bar.inc(1);
+    });
+    bar.finish_and_clear();
+
+    Ok(Section {
+        directory: config.directory.clone(),
+        tree,
+        files,
+        unmatched_excludes,
+        excluded,
+    })
+}
 
-    if metadata.is_dir() && !should_exclude(path, exclude_patterns) {
-        let entries = fs::read_dir(path)?
-            .filter_map(Result::ok)
-            .collect::<Vec<_>>();
+/// Fails with an actionable message when `path` cannot be processed at all.
+fn check_readable(path: &Path) -> Result<(), String> {
+    let result = fs::metadata(path).and_then(|metadata| match metadata.is_dir() {
+        true => fs::read_dir(path).map(drop),
+        false => fs::File::open(path).map(drop),
+    });
+    result.map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => format!(
+            "{} does not exist, check the --directory argument",
+            path.display()
+        ),
+        io::ErrorKind::PermissionDenied => format!("Permission denied reading {}", path.display()),
+        _ => format!("Could not read {}: {}", path.display(), err),
+    })
+}
+
+/// Lets the user pick from the files that would be included, and restricts
+/// `config` to the picked ones.
+fn pick_files(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
+    if config.directory.is_file() {
+        return Ok(());
+    }
+    let paths: Vec<PathBuf> = select_files(config)?
+        .into_iter()
+        .map(|path| match path.strip_prefix(&config.directory) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        })
+        .collect();
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let picked = MultiSelect::new()
+        .with_prompt(format!(
+            "Files to include from {} (space toggles, enter confirms)",
+            config.directory.display()
+        ))
+        .items(paths.iter().map(|path| path.display()))
+        .max_length(20)
+        .interact_opt()?
+        .ok_or("No files picked, the selection was cancelled")?;
+    config.files = Some(picked.into_iter().map(|i| paths[i].clone()).collect());
+    Ok(())
+}
+
+/// Prints the tree and the selected paths of a directory, without reading
+/// any file.
+fn write_dry_run(out: &mut dyn Write, config: &Config) -> io::Result<()> {
+    let tree = build_tree(config)?;
+    let paths = select_files(config)?;
+    write!(out, "{}", render_tree(config, &tree))?;
+    writeln!(out, "\n{} files would be included:", paths.len())?;
+    for path in &paths {
+        writeln!(out, "{}", display_path(config, &config.directory, path))?;
+    }
+    Ok(())
+}
 
-        for entry in entries {
-            let child_path = entry.path();
-            if !should_exclude(&child_path, exclude_patterns) {
-                let child_tree = build_tree(&child_path, exclude_patterns)?;
-                root.add_child(child_tree);
+fn copy_to_clipboard(text: &str) -> Result<(), arboard::Error> {
+    arboard::Clipboard::new()?.set_text(text)
+}
+
+/// Replaces `$NAME` and `${NAME}` with the values of environment variables,
+/// and `$$` with a single `$`. Unset variables are an error rather than
+/// silently empty, and a `$` not starting a name is kept.
+fn expand_env(text: &str) -> Result<String, String> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, next) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err("Unclosed '${' in the prompt".to_string()),
+            }
+        } else if let Some(after) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        } else {
+            let end = after.find(|c| !is_name(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        // Names start with a letter or `_`, so prices like `$5` stay as they are
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        match std::env::var(name) {
+            Ok(value) => expanded.push_str(&value),
+            Err(_) => {
+                return Err(format!(
+                    "Environment variable {} used in the prompt is not set",
+                    name
+                ))
             }
         }
+        rest = next;
     }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
-    Ok(root)
+/// The text of `--prepend` or `--append`: given as is, or read from the file
+/// after an `@`. It always ends with a newline, so it stays on its own lines.
+fn read_raw_text(value: Option<&String>) -> Result<Option<String>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let mut text = match value.strip_prefix('@') {
+        Some(path) => {
+            fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path, err))?
+        }
+        None => value.clone(),
+    };
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok(Some(text))
 }
 
-fn print_tree_item(item: &TreeItem, prefix: &str, is_last: bool) {
-    let connector = if is_last { "└── " } else { "├── " };
-    println!("{}{}{}", prefix, connector, item.name);
+/// Loads the `--config` file, or `promptify.toml` in the current directory if
+/// there is one.
+fn load_config(path: Option<&String>) -> Result<Config, Box<dyn std::error::Error>> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(CONFIG_FILE).is_file() => PathBuf::from(CONFIG_FILE),
+        None => return Ok(Config::default()),
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    Ok(toml::from_str(&text)
+        .map_err(|err| format!("Invalid config {}: {}", path.display(), err))?)
+}
 
-    let new_prefix = if is_last {
-        format!("{}    ", prefix)
+/// Parses a comma-separated list of glob patterns, warning about invalid ones.
+fn parse_patterns(list: Option<&String>, verbosity: Verbosity) -> Vec<Pattern> {
+    list.map(|e| compile_patterns(split_patterns(e), verbosity))
+        .unwrap_or_default()
+}
+
+/// Compiles glob patterns, skipping invalid ones with a warning unless quiet.
+fn compile_patterns(
+    patterns: impl IntoIterator<Item = String>,
+    verbosity: Verbosity,
+) -> Vec<Pattern> {
+    patterns
+        .into_iter()
+        .filter_map(|pattern| {
+            Pattern::new(&pattern)
+                .map_err(|err| {
+                    if verbosity > Verbosity::Quiet {
+                        eprintln!("Warning: Invalid glob pattern '{}': {}", pattern, err);
+                    }
+                    err
+                })
+                .ok()
+        })
+        .collect()
+}
+
+/// Splits a comma-separated list of names, lower cased.
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Parses a comma-separated list of `ext=lang` pairs, warning about invalid
+/// ones unless quiet.
+fn parse_lang_map(list: Option<&String>, verbosity: Verbosity) -> HashMap<String, String> {
+    list.map(|list| {
+        list.split(',')
+            .filter_map(|pair| match pair.split_once('=') {
+                Some((ext, lang)) if !ext.trim().is_empty() => Some((
+                    ext.trim().trim_start_matches('.').to_lowercase(),
+                    lang.trim().to_string(),
+                )),
+                _ => {
+                    if verbosity > Verbosity::Quiet {
+                        eprintln!(
+                            "Warning: Invalid language mapping '{}', expected ext=lang",
+                            pair
+                        );
+                    }
+                    None
+                }
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Reads newline-separated paths into `config.files` from a file, or from
+/// stdin when `list` is `-`, along with the line ranges given as
+/// `path:40-80`. The ranges of a path listed more than once are combined.
+fn read_file_list(list: &str, config: &mut Config) -> io::Result<()> {
+    let mut content = String::new();
+    if list == "-" {
+        io::stdin().read_to_string(&mut content)?;
+    } else {
+        content = fs::read_to_string(list)?;
+    }
+
+    let mut files = vec![];
+    let mut line_ranges: HashMap<PathBuf, Vec<LineRange>> = HashMap::new();
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let (path, ranges) =
+            parse_file_spec(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !files.contains(&path) {
+            files.push(path.clone());
+        }
+        if !ranges.is_empty() {
+            line_ranges.entry(path).or_default().extend(ranges);
+        }
+    }
+    config.files = Some(files);
+    config.line_ranges = line_ranges;
+    Ok(())
+}
+
+/// Where `--cache` keeps its files: the platform's user cache directory.
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
     } else {
-        format!("{}│   ", prefix)
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
     };
+    base.map(|base| base.join("promptify"))
+}
 
-    for (i, child) in item.children.iter().enumerate() {
-        let is_last_child = i == item.children.len() - 1;
-        print_tree_item(child, &new_prefix, is_last_child);
+/// Shallow-clones `url` into a new temporary directory, returning it and the
+/// path of the checkout inside it.
+fn clone_repo(url: &str) -> io::Result<(TempDir, PathBuf)> {
+    let temp_dir = TempDir::new()?;
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .map(|name| name.trim_end_matches(".git"))
+        .filter(|name| !name.is_empty())
+        .unwrap_or("repo");
+    let directory = temp_dir.path().join(name);
+    let output = process::Command::new("git")
+        .args(["clone", "--depth", "1", "--quiet", "--", url])
+        .arg(&directory)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git clone {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
     }
+    Ok((temp_dir, directory))
 }
 
-fn print_tree(path: &str, exclude_patterns: &[Pattern]) -> Result<(), Box<dyn std::error::Error>> {
-    let path = std::path::Path::new(path);
-    let tree = build_tree(path, exclude_patterns)?;
+/// Lists the files under `directory` that differ from `reference`, relative
+/// to `directory`. Deleted files are left out.
+fn git_changed_files(directory: &Path, reference: &str) -> io::Result<Vec<PathBuf>> {
+    let output = process::Command::new("git")
+        .args([
+            "diff",
+            "--name-only",
+            "--relative",
+            "--diff-filter=d",
+            reference,
+            "--",
+        ])
+        .current_dir(directory)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git diff {} failed: {}",
+            reference,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
 
-    println!("{}", path.display());
-    for (i, child) in tree.children.iter().enumerate() {
-        let is_last_child = i == tree.children.len() - 1;
-        print_tree_item(child, "", is_last_child);
+/// Parses a byte count with an optional `K`, `M` or `G` suffix (powers of 1024).
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match value[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        suffix => return Err(format!("unknown size suffix '{}'", suffix)),
+    };
+    let number: f64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    if number < 0.0 {
+        return Err(format!("invalid size '{}'", value));
     }
+    Ok((number * multiplier as f64) as u64)
+}
 
-    Ok(())
+/// Prints what was included to stderr: file and byte counts, files per
+/// language and the largest files.
+fn report_stats(files: &[(String, &FileEntry)]) {
+    const LARGEST: usize = 5;
+
+    let sizes: Vec<(&str, usize)> = files
+        .iter()
+        .map(|(shown, file)| (shown.as_str(), file.content.as_ref().map_or(0, String::len)))
+        .collect();
+    let total: usize = sizes.iter().map(|(_, size)| size).sum();
+    eprintln!(
+        "Included {} files, {}",
+        files.len(),
+        format_size(total as u64)
+    );
+
+    let mut languages: BTreeMap<String, usize> = BTreeMap::new();
+    for (_, file) in files {
+        let language = match file.language.as_str() {
+            "" => file
+                .path
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "(no extension)".to_string()),
+            language => language.to_string(),
+        };
+        *languages.entry(language).or_default() += 1;
+    }
+    for (language, count) in &languages {
+        eprintln!("{:>10}  {}", count, language);
+    }
+
+    let mut largest = sizes;
+    largest.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+    eprintln!("Largest files:");
+    for (shown, size) in largest.iter().take(LARGEST) {
+        eprintln!("{:>10}  {}", format_size(*size as u64), shown);
+    }
 }
 
-fn list_dir_recursive(path: &Path, exclude_patterns: &[Pattern]) -> io::Result<()> {
-    if path.is_dir() {
-        let entries = fs::read_dir(path)?;
-        for entry in entries {
-            match entry {
-                Ok(entry) => {
-                    let entry_path = entry.path();
-                    if !should_exclude(&entry_path, exclude_patterns) {
-                        if entry_path.is_dir() {
-                            list_dir_recursive(&entry_path, exclude_patterns)?;
-                        } else if is_plain_text_file(&entry_path) {
-                            if let Some(_entry_str) = entry_path.to_str() {
-                                match read_file(&entry_path) {
-                                    Ok(content) => {
-                                        let lang = match entry_path
-                                            .extension()
-                                            .and_then(|ext| ext.to_str())
-                                        {
-                                            // Web development
-                                            Some("ts") | Some("tsx") => "typescript",
-                                            Some("js") | Some("jsx") => "javascript",
-                                            Some("json") => "json",
-                                            Some("html") | Some("htm") => "html",
-                                            Some("css") => "css",
-                                            Some("scss") | Some("sass") => "scss",
-
-                                            // Template files
-                                            Some("twig") => "twig",
-                                            Some("ejs") => "ejs",
-                                            Some("hbs") => "handlebars",
-                                            Some("vue") => "vue",
-                                            Some("svelte") => "svelte",
-
-                                            // Config files
-                                            Some("yml") | Some("yaml") => "yaml",
-                                            Some("toml") => "toml",
-                                            Some("ini") => "ini",
-                                            Some("env") => "dotenv",
-
-                                            // Documentation
-                                            Some("md") | Some("markdown") => "markdown",
-                                            Some("txt") => "",
-                                            Some("rst") => "restructuredtext",
-
-                                            // Other programming languages
-                                            Some("py") => "python",
-                                            Some("rb") => "ruby",
-                                            Some("php") => "php",
-                                            Some("java") => "java",
-                                            Some("go") => "go",
-                                            Some("rs") => "rust",
-                                            Some("c") => "c",
-                                            Some("cpp") => "cpp",
-                                            Some("h") | Some("hpp") => "cpp",
-                                            Some("sh") | Some("bash") => "bash",
-
-                                            _ => "",
-                                        };
-
-                                        println!("- {:?}:", entry_path);
-                                        println!("```{}", lang);
-                                        println!("{}", content);
-                                        println!("```");
-                                        println!();
-                                    }
-                                    Err(e) => {
-                                        println!("Error reading file {:?}: {}", entry_path, e)
-                                    }
-                                }
-                            }
-                        } else {
-                            eprintln!("ERROR: {:#?} is not plaintext...", entry_path)
-                        }
-                    }
-                }
-                Err(e) => println!("Error reading entry: {}", e),
-            }
+/// Prints how many files the walks left out to stderr, in total and for each
+/// reason, with the largest excluded entries as examples.
+fn report_excluded(config: &Config, sections: &[Section]) {
+    const EXAMPLES: usize = 3;
+
+    let mut reasons: BTreeMap<ExclusionReason, Vec<(String, &Excluded)>> = BTreeMap::new();
+    for section in sections {
+        for excluded in &section.excluded {
+            let shown = display_path(config, &section.directory, &excluded.path);
+            reasons
+                .entry(excluded.reason)
+                .or_default()
+                .push((shown, excluded));
+        }
+    }
+    let totals = |entries: &[(String, &Excluded)]| {
+        let files: usize = entries.iter().map(|(_, excluded)| excluded.files).sum();
+        let bytes: u64 = entries.iter().map(|(_, excluded)| excluded.bytes).sum();
+        format!("{} files, {}", files, format_size(bytes))
+    };
+    let all: Vec<(String, &Excluded)> = reasons.values().flatten().cloned().collect();
+    eprintln!("Excluded {}", totals(&all));
+    for (reason, mut entries) in reasons {
+        entries.sort_by_key(|(_, excluded)| std::cmp::Reverse(excluded.bytes));
+        let mut examples: Vec<String> = entries
+            .iter()
+            .take(EXAMPLES)
+            .map(|(shown, _)| shown.clone())
+            .collect();
+        if entries.len() > EXAMPLES {
+            examples.push("...".to_string());
+        }
+        eprintln!(
+            "  {}: {} ({})",
+            reason,
+            totals(&entries),
+            examples.join(", ")
+        );
+    }
+}
+
+/// A counter for `model`, warning unless quiet when the model is unknown.
+fn token_counter(
+    model: &str,
+    verbosity: Verbosity,
+) -> Result<TokenCounter, Box<dyn std::error::Error>> {
+    let counter = TokenCounter::new(model)?;
+    if counter.estimated() && verbosity > Verbosity::Quiet {
+        eprintln!(
+            "Warning: Unknown model '{}', estimating tokens with cl100k_base",
+            model
+        );
+    }
+    Ok(counter)
+}
+
+/// Prints the estimated input cost of a rendered document to stderr, at
+/// `price` USD per million tokens.
+fn report_cost(counter: &TokenCounter, price: f64, rendered: &str) {
+    let tokens = counter.count(rendered);
+    eprintln!(
+        "Estimated cost ({}): ${:.4} for {} input tokens at ${}/M",
+        counter.model(),
+        tokens as f64 * price / 1_000_000.0,
+        tokens,
+        price
+    );
+}
+
+/// Prints the token summary of a rendered document to stderr.
+fn report_tokens(
+    counter: &TokenCounter,
+    rendered: &str,
+    config: &Config,
+    files: &[(String, &FileEntry)],
+) {
+    let total = counter.count(rendered);
+    eprintln!("Token count ({}): {} tokens", counter.model(), total);
+    for (shown, file) in files {
+        let tokens = counter.count(&render_file(config, file));
+        eprintln!("{:>10}  {}", tokens, shown);
+    }
+
+    if let Some(context_size) = counter.context_size() {
+        if total > context_size && config.verbosity > Verbosity::Quiet {
+            eprintln!(
+                "Warning: {} tokens exceeds the {} token context window of {}",
+                total,
+                context_size,
+                counter.model()
+            );
         }
     }
-    Ok(())
 }
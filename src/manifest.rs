@@ -0,0 +1,65 @@
+//! Manifests of the included files, for `--manifest`.
+
+use crate::{entry_path, format_timestamp, Config, Section};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+/// What a run included, to check later exactly what a model was given.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// Version of promptify that made the manifest.
+    pub version: String,
+    /// When the manifest was made, as a UTC `YYYY-MM-DDTHH:MM:SSZ` timestamp.
+    pub created: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+    /// The path as displayed in the output.
+    pub path: String,
+    pub size: u64,
+    /// Hex SHA-256 of the file on disk, before any transformation.
+    pub sha256: String,
+    pub language: String,
+}
+
+/// Lists the files of `sections` that could be read, hashing them as they
+/// are on disk.
+pub fn build_manifest(config: &Config, sections: &[Section]) -> Manifest {
+    let mut files = vec![];
+    for section in sections {
+        for file in section.files.iter().filter(|file| file.content.is_some()) {
+            let Ok(bytes) = fs::read(&file.path) else {
+                continue;
+            };
+            files.push(ManifestFile {
+                path: entry_path(config, sections, section, &file.path),
+                size: bytes.len() as u64,
+                sha256: Sha256::digest(&bytes)
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+                language: file.language.clone(),
+            });
+        }
+    }
+    Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created: format_timestamp(SystemTime::now()),
+        files,
+    }
+}
+
+/// Writes the manifest of `sections` as pretty-printed JSON.
+pub fn write_manifest(
+    config: &Config,
+    sections: &[Section],
+    mut out: impl Write,
+) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut out, &build_manifest(config, sections))?;
+    writeln!(out)
+}
@@ -0,0 +1,47 @@
+//! Compact JSON and YAML, for `--minify`.
+//!
+//! JSON keeps its exact text, only the whitespace between tokens is dropped.
+//! YAML is parsed and written back as single-line JSON, which is also valid
+//! YAML, losing comments and anchors but not meaning. Content that does not
+//! parse is left alone rather than mangled.
+
+use serde::de::IgnoredAny;
+use serde_json::Value;
+
+/// The compacted `content` of a file in `language`, or `None` when it is
+/// neither JSON nor YAML or does not parse.
+pub(crate) fn minify(content: &str, language: &str) -> Option<String> {
+    match language {
+        "json" => minify_json(content),
+        "yaml" => minify_yaml(content),
+        _ => None,
+    }
+}
+
+fn minify_json(content: &str) -> Option<String> {
+    serde_json::from_str::<IgnoredAny>(content).ok()?;
+    let mut minified = String::with_capacity(content.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in content.chars() {
+        if in_string {
+            minified.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if !c.is_whitespace() {
+            in_string = c == '"';
+            minified.push(c);
+        }
+    }
+    minified.push('\n');
+    Some(minified)
+}
+
+fn minify_yaml(content: &str) -> Option<String> {
+    let value: Value = serde_norway::from_str(content).ok()?;
+    Some(serde_json::to_string(&value).ok()? + "\n")
+}
@@ -0,0 +1,91 @@
+//! Jupyter notebook (`.ipynb`) conversion.
+//!
+//! Notebooks are JSON documents, so instead of dumping them verbatim their
+//! cells are turned into markdown: markdown cells as prose, code cells as
+//! fenced code in the notebook's language, and optionally their outputs.
+
+use serde_json::Value;
+use std::path::Path;
+
+pub(crate) fn is_notebook(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ipynb"))
+}
+
+/// Converts a notebook to markdown, or returns `None` when `content` is not
+/// a notebook.
+pub(crate) fn extract_cells(content: &str, outputs: bool) -> Option<String> {
+    let notebook: Value = serde_json::from_str(content).ok()?;
+    let cells = notebook.get("cells")?.as_array()?;
+    let metadata = notebook.get("metadata");
+    let language = metadata
+        .and_then(|metadata| metadata.pointer("/kernelspec/language"))
+        .or_else(|| metadata.and_then(|metadata| metadata.pointer("/language_info/name")))
+        .and_then(Value::as_str)
+        .unwrap_or("python");
+
+    let mut blocks = vec![];
+    for cell in cells {
+        let source = text(cell.get("source"));
+        match cell.get("cell_type").and_then(Value::as_str) {
+            Some("markdown") | Some("raw") => blocks.push(source.trim_end().to_string()),
+            Some("code") => {
+                blocks.push(fenced(language, &source));
+                if outputs {
+                    for output in cell
+                        .get("outputs")
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten()
+                    {
+                        if let Some(output) = output_text(output) {
+                            blocks.push(format!("Output:\n{}", fenced("", &output)));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks.retain(|block| !block.is_empty());
+    Some(blocks.join("\n\n") + "\n")
+}
+
+/// The text of an output that has one: streams, plain text results and errors.
+fn output_text(output: &Value) -> Option<String> {
+    let text = match output.get("output_type").and_then(Value::as_str)? {
+        "stream" => text(output.get("text")),
+        "execute_result" | "display_data" => text(output.pointer("/data/text~1plain")),
+        "error" => format!(
+            "{}: {}",
+            output
+                .get("ename")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+            output
+                .get("evalue")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+        ),
+        _ => return None,
+    };
+    Some(text).filter(|text| !text.trim().is_empty())
+}
+
+/// Notebook text fields are either a string or a list of lines.
+fn text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+fn fenced(language: &str, code: &str) -> String {
+    let code = code.trim_end();
+    if code.is_empty() {
+        return String::new();
+    }
+    let fence = crate::code_fence(code);
+    format!("{}{}\n{}\n{}", fence, language, code, fence)
+}
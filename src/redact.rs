@@ -0,0 +1,148 @@
+//! Secret masking for `--redact`.
+//!
+//! Two rules, both deliberately simple so they are easy to predict:
+//!
+//! - `KEY=value` and `key: value` lines: in dotenv files every value is
+//!   masked, in ini, YAML and TOML files only values whose key names a
+//!   secret (see [`SECRET_KEY_WORDS`]).
+//! - Anywhere, words that look like access tokens (see [`TOKEN_PREFIXES`])
+//!   or long base64 blobs mixing upper case, lower case and digits.
+
+/// Replaces a masked value.
+pub(crate) const REDACTED: &str = "***REDACTED***";
+
+/// Keys with a part (split at `_`, `.` and `-`) ending in one of these hold
+/// secrets, e.g. `API_KEY` or `db.password`, but not `keywords` or `author`.
+const SECRET_KEY_WORDS: &[&str] = &[
+    "key",
+    "secret",
+    "token",
+    "password",
+    "passwd",
+    "pwd",
+    "credential",
+    "auth",
+    "private",
+];
+
+/// Well-known token formats: AWS access keys, GitHub, GitLab, Slack and
+/// OpenAI/Stripe style keys.
+const TOKEN_PREFIXES: &[&str] = &[
+    "AKIA",
+    "ASIA",
+    "ghp_",
+    "gho_",
+    "ghu_",
+    "ghs_",
+    "ghr_",
+    "github_pat_",
+    "glpat-",
+    "xoxa-",
+    "xoxb-",
+    "xoxp-",
+    "xoxr-",
+    "xoxs-",
+    "sk-",
+    "sk_live_",
+    "rk_live_",
+];
+
+/// Characters after a prefix before a word counts as a token.
+const MIN_TOKEN_SUFFIX: usize = 16;
+
+/// Length from which a mixed-case alphanumeric word counts as a base64 blob.
+const MIN_BLOB_LEN: usize = 40;
+
+/// Which `key = value` lines of a file have their value masked.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Assignments {
+    /// Not a key-value file, only tokens are masked.
+    None,
+    /// Values of keys that name a secret.
+    SecretKeys,
+    /// Every value, as in `.env` files.
+    All,
+}
+
+/// Masks secret-looking values in `content`, keeping the keys and the line
+/// structure intact.
+pub(crate) fn redact(content: &str, assignments: Assignments) -> String {
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let (text, newline) = match line.strip_suffix('\n') {
+                Some(text) => (text, "\n"),
+                None => (line, ""),
+            };
+            let text = redact_assignment(text, assignments).unwrap_or_else(|| redact_tokens(text));
+            text + newline
+        })
+        .collect()
+}
+
+/// Masks the value of a `key=value` or `key: value` line, if it should be.
+fn redact_assignment(line: &str, assignments: Assignments) -> Option<String> {
+    if assignments == Assignments::None {
+        return None;
+    }
+    let rest = line.trim_start();
+    let rest = rest.strip_prefix("export ").unwrap_or(rest);
+    // YAML list items
+    let rest = rest.strip_prefix("- ").unwrap_or(rest);
+
+    let key_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "_.-".contains(c)))
+        .unwrap_or(rest.len());
+    let (key, after_key) = rest.split_at(key_len);
+    let value = after_key
+        .trim_start()
+        .strip_prefix(['=', ':'])?
+        .trim_start();
+    if key.is_empty() || value.trim_end().is_empty() || value.starts_with('#') {
+        return None;
+    }
+
+    let lower = key.to_ascii_lowercase();
+    let secret = assignments == Assignments::All
+        || lower
+            .split(['_', '.', '-'])
+            .any(|part| SECRET_KEY_WORDS.iter().any(|word| part.ends_with(word)));
+    if !secret {
+        return None;
+    }
+    let value_start = line.len() - value.len();
+    Some(format!("{}{}", &line[..value_start], REDACTED))
+}
+
+/// Masks every word of `line` that looks like a token.
+fn redact_tokens(line: &str) -> String {
+    let is_token_char = |c: char| c.is_ascii_alphanumeric() || "+=_-".contains(c);
+    let mut redacted = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(is_token_char) {
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_token_char(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        redacted.push_str(if looks_like_token(word) {
+            REDACTED
+        } else {
+            word
+        });
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+fn looks_like_token(word: &str) -> bool {
+    let prefixed = TOKEN_PREFIXES.iter().any(|prefix| {
+        word.strip_prefix(prefix)
+            .is_some_and(|suffix| suffix.len() >= MIN_TOKEN_SUFFIX)
+    });
+    prefixed
+        || (word.len() >= MIN_BLOB_LEN
+            && word.contains(|c: char| c.is_ascii_uppercase())
+            && word.contains(|c: char| c.is_ascii_lowercase())
+            && word.contains(|c: char| c.is_ascii_digit()))
+}
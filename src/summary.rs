@@ -0,0 +1,426 @@
+//! Declaration outlines for `--summary-only`.
+//!
+//! Rust files keep their items with the bodies of functions replaced by
+//! `{ ... }`, the full definitions of structs and enums, and `impl`, `trait`
+//! and `mod` blocks holding the outlines of their own items. Python files
+//! keep the `def` and `class` lines of the module and of its classes. The
+//! extraction is line based rather than a real parser, so unusual formatting
+//! may leave the odd declaration out.
+
+use crate::comments::strip_comments;
+use crate::imports::strip_visibility;
+
+/// The outline of `content` in `language`, or `None` when the language is not
+/// supported.
+pub(crate) fn summarize(content: &str, language: &str) -> Option<String> {
+    let content = strip_comments(content, language)?;
+    let lines = match language {
+        "rust" => Rust::default().run(&content),
+        "python" => python(&content),
+        _ => return None,
+    };
+    let mut summary = lines.join("\n");
+    if !summary.is_empty() {
+        summary.push('\n');
+    }
+    Some(summary)
+}
+
+/// What an item does with the body that follows its signature.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Functions and macros, whose body is left out.
+    Function,
+    /// `impl`, `trait`, `mod` and `extern` blocks, whose items are outlined.
+    Container,
+    /// Structs, enums and unions, whose body is kept in full.
+    Definition,
+    /// Constants, statics and type aliases, of which the value is left out.
+    Value,
+    /// `pub use` lines, kept as they are.
+    Export,
+}
+
+/// The kind of opened brace.
+enum Block {
+    /// A container, with the indentation its closing brace is written with.
+    Container(String),
+    Other,
+}
+
+/// A signature spanning the lines read so far.
+struct Pending {
+    kind: Kind,
+    indent: String,
+    text: String,
+}
+
+#[derive(Default)]
+struct Rust {
+    blocks: Vec<Block>,
+    pending: Option<Pending>,
+    /// How many blocks are open around the definition being copied.
+    copying: Option<usize>,
+    lines: Vec<String>,
+}
+
+impl Rust {
+    fn run(mut self, content: &str) -> Vec<String> {
+        let masked = mask_rust_literals(content);
+        for (line, masked) in content.lines().zip(masked.lines()) {
+            self.line(line, masked);
+        }
+        self.lines
+    }
+
+    fn line(&mut self, line: &str, masked: &str) {
+        if let Some(depth) = self.copying {
+            self.lines.push(line.trim_end().to_string());
+            self.braces(masked);
+            if self.blocks.len() <= depth {
+                self.copying = None;
+            }
+            return;
+        }
+
+        let outlined = self
+            .blocks
+            .iter()
+            .all(|block| matches!(block, Block::Container(_)));
+        let pending = match self.pending.take() {
+            Some(pending) => Some(pending),
+            None if outlined => rust_item_kind(masked.trim_start()).map(|kind| Pending {
+                kind,
+                indent: line[..line.len() - line.trim_start().len()].to_string(),
+                text: String::new(),
+            }),
+            None => None,
+        };
+        let Some(mut pending) = pending else {
+            self.braces(masked);
+            return;
+        };
+
+        let Some((end, terminator)) = rust_signature_end(masked, pending.kind) else {
+            push_word(&mut pending.text, line);
+            self.pending = Some(pending);
+            return;
+        };
+        push_word(&mut pending.text, &line[..end]);
+        let signature = tidy_signature(&pending.indent, &pending.text);
+        let rest = &masked[end..];
+        match (terminator, pending.kind) {
+            ('{', Kind::Container) => {
+                self.lines.push(format!("{} {{", signature));
+                self.blocks.push(Block::Container(pending.indent));
+                self.braces(&rest[1..]);
+            }
+            ('{', Kind::Definition) => {
+                self.lines
+                    .push(format!("{} {}", signature, line[end..].trim_end()));
+                let depth = self.blocks.len();
+                self.braces(rest);
+                if self.blocks.len() > depth {
+                    self.copying = Some(depth);
+                }
+            }
+            ('{', _) => {
+                self.lines.push(format!("{} {{ ... }}", signature));
+                self.braces(rest);
+            }
+            ('=', _) => {
+                self.lines.push(format!("{} = ...;", signature));
+                self.braces(rest);
+            }
+            _ => self.lines.push(format!("{};", signature)),
+        }
+    }
+
+    /// Opens and closes blocks for the braces of a masked line, writing the
+    /// closing brace of containers.
+    fn braces(&mut self, masked: &str) {
+        for c in masked.chars() {
+            match c {
+                '{' => self.blocks.push(Block::Other),
+                '}' => {
+                    if let Some(Block::Container(indent)) = self.blocks.pop() {
+                        self.lines.push(format!("{}}}", indent));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The kind of item a masked, left-trimmed line starts, if any.
+fn rust_item_kind(line: &str) -> Option<Kind> {
+    let public = line.starts_with("pub");
+    let mut rest = strip_visibility(line);
+    loop {
+        let stripped = ["async ", "unsafe ", "default "]
+            .iter()
+            .find_map(|qualifier| rest.strip_prefix(qualifier))
+            .or_else(|| {
+                let after = rest.strip_prefix("const ")?;
+                ["fn ", "unsafe ", "async ", "extern "]
+                    .iter()
+                    .any(|next| after.starts_with(next))
+                    .then_some(after)
+            });
+        match stripped {
+            Some(stripped) => rest = stripped.trim_start(),
+            None => break,
+        }
+    }
+    if let Some(after) = rest.strip_prefix("extern") {
+        let after = after.trim_start();
+        // Skip the ABI, such as `"C"`, whose contents are masked
+        let after = match after.strip_prefix('"') {
+            Some(abi) => abi
+                .split_once('"')
+                .map_or("", |(_, rest)| rest)
+                .trim_start(),
+            None => after,
+        };
+        return match after {
+            _ if after.starts_with("fn ") => Some(Kind::Function),
+            _ if after.starts_with('{') => Some(Kind::Container),
+            _ => None,
+        };
+    }
+    let word = rest
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '!'))
+        .next()
+        .unwrap_or_default();
+    match word {
+        "fn" | "macro_rules!" => Some(Kind::Function),
+        "impl" | "trait" | "mod" => Some(Kind::Container),
+        "struct" | "enum" | "union" => Some(Kind::Definition),
+        "const" | "static" | "type" => Some(Kind::Value),
+        "use" if public => Some(Kind::Export),
+        _ => None,
+    }
+}
+
+/// Where the signature of an item of `kind` ends on a masked line: at a `{`
+/// or `;`, or at the `=` of a value, outside of parentheses and brackets.
+/// Exports only end at their `;`, as their braces belong to the use tree.
+fn rust_signature_end(masked: &str, kind: Kind) -> Option<(usize, char)> {
+    let mut depth = 0usize;
+    for (i, c) in masked.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ';' if depth == 0 => return Some((i, c)),
+            '{' if depth == 0 && kind != Kind::Export => return Some((i, c)),
+            '=' if depth == 0 && kind == Kind::Value => {
+                // Not the `=` of `=>` or `==`
+                let next = masked[i + 1..].chars().next();
+                if !matches!(next, Some('>' | '=')) {
+                    return Some((i, c));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Adds a line of a signature, turning line breaks into spaces.
+fn push_word(text: &mut String, line: &str) {
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+    if !text.is_empty() {
+        text.push(' ');
+    }
+    text.push_str(line);
+}
+
+/// A signature joined from several lines, without the spaces and trailing
+/// commas left by the line breaks.
+fn tidy_signature(indent: &str, text: &str) -> String {
+    let text = text
+        .trim_end()
+        .replace("( ", "(")
+        .replace(", )", ")")
+        .replace(",)", ")");
+    format!("{}{}", indent, text)
+}
+
+/// `content` with the contents of string and character literals replaced by
+/// spaces, byte for byte, so braces inside them are not counted.
+fn mask_rust_literals(content: &str) -> String {
+    let chars: Vec<(usize, char)> = content.char_indices().collect();
+    let mut masked = content.as_bytes().to_vec();
+    let mut i = 0;
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let after_word = i > 0 && {
+            let previous = chars[i - 1].1;
+            previous.is_alphanumeric() || previous == '_'
+        };
+        // Raw strings: r"...", r#"..."#
+        if c == 'r' && !after_word {
+            let hashes = chars[i + 1..].iter().take_while(|(_, c)| *c == '#').count();
+            if chars.get(i + 1 + hashes).is_some_and(|(_, c)| *c == '"') {
+                let open = i + 1 + hashes;
+                let close = (open + 1..chars.len()).find(|&j| {
+                    chars[j].1 == '"'
+                        && (1..=hashes).all(|k| chars.get(j + k).is_some_and(|(_, c)| *c == '#'))
+                });
+                // An unclosed raw string runs to the end of the file
+                let Some(close) = close else {
+                    blank(chars[open].0 + 1, content.len(), &mut masked);
+                    break;
+                };
+                blank(chars[open].0 + 1, chars[close].0, &mut masked);
+                i = close + 1 + hashes;
+                continue;
+            }
+        }
+        match c {
+            '"' => {
+                let mut j = i + 1;
+                while j < chars.len() && chars[j].1 != '"' {
+                    j += if chars[j].1 == '\\' { 2 } else { 1 };
+                }
+                let to = chars.get(j).map_or(content.len(), |(at, _)| *at);
+                blank(start + 1, to, &mut masked);
+                i = j + 1;
+            }
+            // Character literals, but not lifetimes such as `'a`
+            '\'' => {
+                let end = match chars.get(i + 1) {
+                    Some((_, '\\')) => (i + 2..chars.len()).find(|&j| chars[j].1 == '\''),
+                    Some(_) => chars.get(i + 2).filter(|(_, c)| *c == '\'').map(|_| i + 2),
+                    None => None,
+                };
+                match end {
+                    Some(end) => {
+                        blank(start + 1, chars[end].0, &mut masked);
+                        i = end + 1;
+                    }
+                    None => i += 1,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    // Whole characters were replaced, so the bytes are still UTF-8
+    String::from_utf8(masked).unwrap_or_else(|_| content.to_string())
+}
+
+fn blank(from: usize, to: usize, masked: &mut [u8]) {
+    if from >= to {
+        return;
+    }
+    for byte in &mut masked[from..to] {
+        if *byte != b'\n' {
+            *byte = b' ';
+        }
+    }
+}
+
+/// The `def` and `class` lines of Python source, of the module and of its
+/// classes, with `...` for the bodies of functions.
+fn python(content: &str) -> Vec<String> {
+    let mut lines = vec![];
+    // Indentation of the open definitions, and whether each is a class
+    let mut scopes: Vec<(usize, bool)> = vec![];
+    let mut decorators = vec![];
+    let mut pending: Option<(bool, String)> = None;
+    let mut depth = 0usize;
+    let mut in_string: Option<&str> = None;
+
+    for line in content.lines() {
+        // Lines inside triple-quoted strings are not code
+        if let Some(quote) = in_string {
+            if line.matches(quote).count() % 2 == 1 {
+                in_string = None;
+            }
+            continue;
+        }
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if let Some((class, mut text)) = pending.take() {
+            push_word(&mut text, trimmed);
+            match python_signature_end(trimmed, &mut depth) {
+                Some(end) => lines.push(python_signature(
+                    class,
+                    &text,
+                    trimmed.trim_end().len() - end,
+                )),
+                None => pending = Some((class, text)),
+            }
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        for quote in ["\"\"\"", "'''"] {
+            if line.matches(quote).count() % 2 == 1 {
+                in_string = Some(quote);
+            }
+        }
+
+        while scopes.last().is_some_and(|(open, _)| *open >= indent) {
+            scopes.pop();
+        }
+        let class = trimmed.starts_with("class ");
+        let function = trimmed.starts_with("def ") || trimmed.starts_with("async def ");
+        if trimmed.starts_with('@') {
+            decorators.push(line.trim_end().to_string());
+            continue;
+        }
+        if !class && !function {
+            decorators.clear();
+            continue;
+        }
+        let outlined = scopes.iter().all(|(_, class)| *class);
+        scopes.push((indent, class && outlined));
+        if !outlined {
+            decorators.clear();
+            continue;
+        }
+        lines.append(&mut decorators);
+        let text = line.trim_end().to_string();
+        depth = 0;
+        match python_signature_end(trimmed, &mut depth) {
+            Some(end) => lines.push(python_signature(
+                class,
+                &text,
+                trimmed.trim_end().len() - end,
+            )),
+            None => pending = Some((class, text)),
+        }
+    }
+    lines
+}
+
+/// Where a signature ends on a line: just after the `:` outside of any
+/// parentheses or brackets, carrying the nesting `depth` between lines.
+fn python_signature_end(line: &str, depth: &mut usize) -> Option<usize> {
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' | '[' | '{' => *depth += 1,
+            ')' | ']' | '}' => *depth = depth.saturating_sub(1),
+            ':' if *depth == 0 => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A signature cut after its `:`, which is `cut` bytes before the end of
+/// `text`, with `...` standing in for the body of a function.
+fn python_signature(class: bool, text: &str, cut: usize) -> String {
+    let signature = tidy_signature("", &text[..text.len() - cut]);
+    match class {
+        true => signature,
+        false => format!("{} ...", signature),
+    }
+}
@@ -0,0 +1,54 @@
+//! Summaries of CSV and TSV files.
+//!
+//! Data files are often far too long to include whole, and the header with a
+//! few rows is usually enough to understand them. Long tables are cut down to
+//! their header and first rows, followed by a note with the row count.
+
+use std::path::Path;
+
+/// Data rows kept by default.
+pub(crate) const DEFAULT_TABLE_ROWS: usize = 10;
+
+pub(crate) fn is_table(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("tsv"))
+}
+
+/// Keeps the header and the first `rows` data rows of a table, or returns
+/// `None` when it has no more rows than that.
+pub(crate) fn summarize(content: &str, rows: usize) -> Option<String> {
+    let records = records(content);
+    let total = records.len().saturating_sub(1);
+    if total <= rows {
+        return None;
+    }
+    let kept = records[..=rows].join("\n");
+    Some(format!(
+        "{}\n// {} more rows, {} in total\n",
+        kept,
+        total - rows,
+        total
+    ))
+}
+
+/// Splits a table into its records, keeping line breaks inside quoted fields.
+fn records(content: &str) -> Vec<&str> {
+    let mut records = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in content.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                records.push(content[start..i].trim_end_matches('\r'));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < content.len() {
+        records.push(&content[start..]);
+    }
+    records.retain(|record| !record.trim().is_empty());
+    records
+}
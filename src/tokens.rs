@@ -0,0 +1,173 @@
+use crate::{keep_within, part_header, render_file, render_sections, Config, Section};
+use tiktoken_rs::model::get_context_size;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{bpe_for_tokenizer, CoreBPE};
+
+/// Model whose encoding is used for token budgets when no model is named.
+pub const DEFAULT_TOKEN_MODEL: &str = "gpt-4";
+
+/// Input prices in USD per million tokens. Dated model names such as
+/// `gpt-4o-2024-08-06` use the entry with the longest matching prefix.
+const INPUT_PRICES: &[(&str, f64)] = &[
+    ("gpt-4", 30.0),
+    ("gpt-4-32k", 60.0),
+    ("gpt-4-turbo", 10.0),
+    ("gpt-4o", 2.5),
+    ("gpt-4o-mini", 0.15),
+    ("gpt-4.1", 2.0),
+    ("gpt-4.1-mini", 0.4),
+    ("gpt-4.1-nano", 0.1),
+    ("gpt-3.5-turbo", 0.5),
+    ("o1", 15.0),
+    ("o1-mini", 1.1),
+    ("o3-mini", 1.1),
+    ("claude-3-opus", 15.0),
+    ("claude-3-5-sonnet", 3.0),
+    ("claude-3-5-haiku", 0.8),
+    ("claude-3-haiku", 0.25),
+];
+
+/// The built-in input price of `model` in USD per million tokens.
+pub fn input_price(model: &str) -> Option<f64> {
+    INPUT_PRICES
+        .iter()
+        .filter(|(name, _)| model.starts_with(name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|&(_, price)| price)
+}
+
+/// Counts tokens with the BPE encoding used by a model.
+pub struct TokenCounter {
+    model: String,
+    bpe: &'static CoreBPE,
+    context_size: Option<usize>,
+    estimated: bool,
+}
+
+impl TokenCounter {
+    /// `model` may be a model name such as `gpt-4` or an encoding such as
+    /// `cl100k_base`. Unknown models are estimated with `cl100k_base`, see
+    /// [`TokenCounter::estimated`].
+    pub fn new(model: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let tokenizer = match model {
+            "o200k_base" => Some(Tokenizer::O200kBase),
+            "cl100k_base" => Some(Tokenizer::Cl100kBase),
+            "p50k_base" => Some(Tokenizer::P50kBase),
+            "r50k_base" => Some(Tokenizer::R50kBase),
+            _ => get_tokenizer(model),
+        };
+        let estimated = tokenizer.is_none();
+
+        Ok(TokenCounter {
+            model: model.to_string(),
+            bpe: bpe_for_tokenizer(tokenizer.unwrap_or(Tokenizer::Cl100kBase))?,
+            context_size: get_context_size(model),
+            estimated,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Whether the model is unknown, its tokens estimated with `cl100k_base`.
+    pub fn estimated(&self) -> bool {
+        self.estimated
+    }
+
+    /// The model's context window, if it is a known model.
+    pub fn context_size(&self) -> Option<usize> {
+        self.context_size
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// Drops files from the end of the listing once the rendered document would
+/// exceed `max_tokens`, and returns how many were dropped.
+pub fn apply_token_budget(
+    counter: &TokenCounter,
+    max_tokens: usize,
+    config: &Config,
+    sections: &mut [Section],
+) -> usize {
+    // Everything but the files themselves: headings, trees and prompt
+    let skeleton: Vec<Section> = sections
+        .iter()
+        .map(|section| Section {
+            directory: section.directory.clone(),
+            tree: section.tree.clone(),
+            files: vec![],
+            unmatched_excludes: vec![],
+            excluded: vec![],
+        })
+        .collect();
+    let skeleton_tokens = counter.count(&render_sections(config, &skeleton));
+
+    keep_within(
+        sections,
+        max_tokens.saturating_sub(skeleton_tokens),
+        |file| counter.count(&render_file(config, file)),
+    )
+}
+
+/// Splits the files of `sections` into consecutive parts that each render
+/// within `max_tokens`, keeping every file whole. Only the first part shows
+/// the trees, while the prompt is counted in every part since the caller
+/// decides where it goes. A file too large for any part gets one of its own.
+pub fn split_by_tokens(
+    counter: &TokenCounter,
+    max_tokens: usize,
+    config: &Config,
+    sections: Vec<Section>,
+) -> Vec<Vec<Section>> {
+    let skeleton: Vec<Section> = sections
+        .iter()
+        .map(|section| Section {
+            directory: section.directory.clone(),
+            tree: section.tree.clone(),
+            files: vec![],
+            unmatched_excludes: vec![],
+            excluded: vec![],
+        })
+        .collect();
+    // Room for any part number
+    let header_tokens = counter.count(&part_header(config.format, 999, 999));
+    let first_tokens = header_tokens + counter.count(&render_sections(config, &skeleton));
+    let rest_config = Config {
+        show_tree: Some(false),
+        ..config.clone()
+    };
+    let rest_tokens = header_tokens + counter.count(&render_sections(&rest_config, &skeleton));
+
+    let mut parts = vec![skeleton.clone()];
+    let mut total = 0;
+    for (i, section) in sections.into_iter().enumerate() {
+        for file in section.files {
+            let file_tokens = counter.count(&render_file(config, &file));
+            let fixed = if parts.len() == 1 {
+                first_tokens
+            } else {
+                rest_tokens
+            };
+            let part = parts.last().expect("Starts with one part");
+            let has_files = part.iter().any(|section| !section.files.is_empty());
+            if has_files && fixed + total + file_tokens > max_tokens {
+                parts.push(skeleton.clone());
+                total = 0;
+            }
+            total += file_tokens;
+            parts.last_mut().expect("Starts with one part")[i]
+                .files
+                .push(file);
+        }
+    }
+
+    // Later parts only need the directories they list files from
+    for part in parts.iter_mut().skip(1) {
+        part.retain(|section| !section.files.is_empty());
+    }
+    parts
+}
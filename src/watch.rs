@@ -0,0 +1,92 @@
+//! Change detection for `--watch`.
+//!
+//! The tree is polled rather than subscribed to: a [`Snapshot`] notes the
+//! files a run selected and the directories walked on the way, and checking
+//! it again only reads their metadata. Adding, removing or renaming an entry
+//! changes the modification time of its directory, so new files are noticed
+//! without walking the tree on every check. The tree is only walked again
+//! once something changed.
+//!
+//! Events from the `notify` crate would avoid the polling delay and the
+//! checks of large trees, but it is not a dependency; [`wait_for_change`] is
+//! the one place that would have to change to use it.
+
+use crate::{glob_path, walk_files, Config};
+use glob::Pattern;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The size and modification time of a file or directory, `None` once it
+/// cannot be read.
+type Stamp = Option<(u64, Option<SystemTime>)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// The selected files and walked directories of some configs, as they were
+/// when it was taken.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    stamps: HashMap<PathBuf, Stamp>,
+}
+
+impl Snapshot {
+    /// Walks the directories of `configs` for the paths to watch.
+    pub fn take(configs: &[Config]) -> Snapshot {
+        let mut stamps = HashMap::new();
+        for config in configs {
+            // A directory that cannot be walked right now has nothing to compare
+            let walk = walk_files(config).unwrap_or_default();
+            for path in walk.files.into_iter().chain(walk.dirs) {
+                let stamp = stamp(&path);
+                stamps.insert(path, stamp);
+            }
+        }
+        Snapshot { stamps }
+    }
+
+    /// Whether a noted file or directory changed since the snapshot was taken.
+    pub fn changed(&self) -> bool {
+        self.stamps
+            .iter()
+            .any(|(path, noted)| stamp(path) != *noted)
+    }
+}
+
+/// Checks `snapshot` every `interval` until something changes, then waits
+/// until nothing changes for a whole interval, so a burst of saves leads to
+/// a single render. Returns the settled snapshot.
+pub fn wait_for_change(configs: &[Config], snapshot: Snapshot, interval: Duration) -> Snapshot {
+    while !snapshot.changed() {
+        thread::sleep(interval);
+    }
+    loop {
+        let snapshot = Snapshot::take(configs);
+        thread::sleep(interval);
+        if !snapshot.changed() {
+            return snapshot;
+        }
+    }
+}
+
+/// An exclude pattern for a file or directory a run writes, such as the
+/// output, when it lies inside `directory`. It must exist already.
+pub fn written_pattern(directory: &Path, written: &Path) -> Option<Pattern> {
+    let name = written.file_name()?;
+    let parent = match written.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let written = fs::canonicalize(parent).ok()?.join(name);
+    let relative = written
+        .strip_prefix(fs::canonicalize(directory).ok()?)
+        .ok()?;
+    let relative = glob_path(relative);
+    // A leading slash anchors the pattern to the directory
+    Pattern::new(&format!("/{}", Pattern::escape(&relative))).ok()
+}
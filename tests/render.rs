@@ -0,0 +1,1393 @@
+use glob::Pattern;
+use promptify::archive::write_archive;
+use promptify::imports::follow_imports;
+use promptify::manifest::build_manifest;
+use promptify::tokens::{split_by_tokens, TokenCounter};
+use promptify::watch::{wait_for_change, written_pattern, Snapshot};
+use promptify::{
+    build_section, build_tree, collect_files, glob_path, parse_file_spec, read_files, render,
+    render_parts, render_sections, render_template, render_to_writer, render_tree, select_files,
+    split_patterns, text_section, Config, ExclusionReason, Format, Kind, Order, Transform,
+};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Creates a temporary directory holding `files`, given as relative paths
+/// and contents. Paths ending in `/` create empty directories.
+fn fixture(files: &[(&str, &[u8])]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for (path, content) in files {
+        let path = dir.path().join(path);
+        if path.to_string_lossy().ends_with('/') {
+            fs::create_dir_all(&path).unwrap();
+            continue;
+        }
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+    }
+    dir
+}
+
+fn patterns(patterns: &[&str]) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .map(|pattern| Pattern::new(pattern).unwrap())
+        .collect()
+}
+
+fn tree_string(config: &Config) -> String {
+    render_tree(config, &build_tree(config).unwrap())
+}
+
+fn file_names(config: &Config) -> Vec<String> {
+    collect_files(config)
+        .unwrap()
+        .iter()
+        .map(|file| name_in(config.directory.as_path(), &file.path))
+        .collect()
+}
+
+fn name_in(directory: &Path, path: &Path) -> String {
+    path.strip_prefix(directory)
+        .unwrap()
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+#[test]
+fn tree_lists_directories_first_then_files() {
+    let dir = fixture(&[
+        ("b.txt", b"b"),
+        ("a.txt", b"a"),
+        ("src/main.rs", b"fn main() {}\n"),
+        ("src/lib/mod.rs", b""),
+    ]);
+    let config = Config::new(dir.path());
+
+    let expected = format!(
+        "{}\n\
+         ├── src/\n\
+         │   ├── lib/\n\
+         │   │   └── mod.rs\n\
+         │   └── main.rs\n\
+         ├── a.txt\n\
+         └── b.txt\n",
+        dir.path().display()
+    );
+    assert_eq!(tree_string(&config), expected);
+}
+
+#[test]
+fn files_are_listed_in_tree_order() {
+    let dir = fixture(&[
+        ("b.txt", b"b"),
+        ("a.txt", b"a"),
+        ("src/main.rs", b"fn main() {}\n"),
+    ]);
+    let config = Config::new(dir.path());
+
+    assert_eq!(file_names(&config), ["src/main.rs", "a.txt", "b.txt"]);
+}
+
+#[test]
+fn markdown_fences_each_file_with_its_language() {
+    let dir = fixture(&[("src/main.rs", b"fn main() {}\n"), ("notes.txt", b"hello")]);
+    let config = Config::new(dir.path());
+    let output = render(&config).unwrap();
+
+    assert!(output.starts_with("### File Tree:\n"));
+    assert!(output.contains("### Files:\n"));
+    assert!(output.contains("- src/main.rs:\n```rust\nfn main() {}\n\n```\n"));
+    assert!(output.contains("- notes.txt:\n```\nhello\n```\n"));
+}
+
+#[test]
+fn fence_is_longer_than_backticks_in_content() {
+    let dir = fixture(&[("README.md", b"```sh\nls\n```\n")]);
+    let config = Config::new(dir.path());
+    let output = render(&config).unwrap();
+
+    assert!(output.contains("````markdown\n```sh\nls\n```\n\n````\n"));
+}
+
+#[test]
+fn detects_languages_by_extension() {
+    let dir = fixture(&[
+        ("a.py", b""),
+        ("b.ts", b""),
+        ("c.toml", b""),
+        ("d.txt", b""),
+    ]);
+    let config = Config::new(dir.path());
+    let languages: Vec<String> = collect_files(&config)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.language)
+        .collect();
+
+    assert_eq!(languages, ["python", "typescript", "toml", ""]);
+}
+
+#[test]
+fn custom_languages_override_the_built_in_ones() {
+    let dir = fixture(&[("page.tpl", b"<p>{{ name }}</p>")]);
+    let mut config = Config::new(dir.path());
+    config
+        .languages
+        .insert("tpl".to_string(), "html".to_string());
+    let files = collect_files(&config).unwrap();
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].language, "html");
+}
+
+#[test]
+fn excluded_globs_are_left_out_of_tree_and_files() {
+    let dir = fixture(&[
+        ("keep.rs", b""),
+        ("debug.log", b"log"),
+        ("target/out.rs", b""),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = patterns(&["*.log", "*/target"]);
+
+    let tree = tree_string(&config);
+    assert!(tree.contains("keep.rs"));
+    assert!(!tree.contains("debug.log"));
+    assert!(!tree.contains("target"));
+    assert_eq!(file_names(&config), ["keep.rs"]);
+}
+
+#[test]
+fn include_patterns_restrict_files_but_excludes_win() {
+    let dir = fixture(&[("a.rs", b""), ("b.rs", b""), ("c.toml", b"")]);
+    let mut config = Config::new(dir.path());
+    config.include_patterns = patterns(&["*.rs"]);
+    config.exclude_patterns = patterns(&["*/b.rs"]);
+
+    assert_eq!(file_names(&config), ["a.rs"]);
+}
+
+#[test]
+fn include_paths_match_relative_to_the_directory() {
+    let dir = fixture(&[
+        ("api/src/a.rs", b""),
+        ("api/src/nested/b.rs", b""),
+        ("api/lib/src/c.rs", b""),
+        ("api/main.rs", b""),
+    ]);
+    let mut config = Config::new(dir.path().join("api"));
+    config.include_patterns = patterns(&["src/*.rs"]);
+    assert_eq!(file_names(&config), ["src/nested/b.rs", "src/a.rs"]);
+
+    config.include_patterns = patterns(&["/main.rs"]);
+    assert_eq!(file_names(&config), ["main.rs"]);
+}
+
+#[test]
+fn empty_directories_are_pruned_unless_kept() {
+    let dir = fixture(&[("empty/", b""), ("logs/old.log", b""), ("main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = patterns(&["*.log"]);
+
+    let tree = tree_string(&config);
+    assert!(!tree.contains("empty"));
+    assert!(!tree.contains("logs"));
+
+    config.keep_empty_dirs = true;
+    let tree = tree_string(&config);
+    assert!(tree.contains("├── empty/\n"));
+    assert!(tree.contains("├── logs/\n"));
+}
+
+#[test]
+fn empty_directory_renders_without_files() {
+    let dir = fixture(&[]);
+    let config = Config::new(dir.path());
+    let output = render(&config).unwrap();
+
+    assert_eq!(
+        output,
+        format!("### File Tree:\n{}\n\n\n### Files:\n", dir.path().display())
+    );
+}
+
+#[test]
+fn binary_files_are_skipped() {
+    let dir = fixture(&[
+        ("image.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+        ("blob", b"\0\x01\x02\x03"),
+        ("script", b"#!/bin/sh\necho hi\n"),
+    ]);
+    let config = Config::new(dir.path());
+
+    assert_eq!(file_names(&config), ["script"]);
+}
+
+#[test]
+fn hidden_entries_are_skipped_by_default() {
+    let dir = fixture(&[(".env", b"KEY=1"), (".git/config", b""), ("main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+
+    assert!(!tree_string(&config).contains(".git"));
+    assert_eq!(file_names(&config), ["main.rs"]);
+
+    config.hidden = true;
+    assert_eq!(file_names(&config), [".git/config", ".env", "main.rs"]);
+}
+
+#[test]
+fn prompt_comes_last() {
+    let dir = fixture(&[("main.rs", b"fn main() {}\n")]);
+    let mut config = Config::new(dir.path());
+    config.prompt = Some("Explain this code.".to_string());
+    let output = render(&config).unwrap();
+
+    assert!(output.ends_with("```\n\n\n\n### Prompt:\nExplain this code.\n"));
+}
+
+#[test]
+fn no_prompt_section_without_a_prompt() {
+    let dir = fixture(&[("main.rs", b"")]);
+    let config = Config::new(dir.path());
+
+    assert!(!render(&config).unwrap().contains("### Prompt:"));
+}
+
+#[test]
+fn depth_limit_truncates_directories() {
+    let dir = fixture(&[("a/b/c.rs", b""), ("top.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.max_depth = Some(1);
+
+    assert!(tree_string(&config).contains("│   └── b/ (...)\n"));
+    assert_eq!(file_names(&config), ["top.rs"]);
+}
+
+#[test]
+fn tree_can_be_left_out() {
+    let dir = fixture(&[("main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.show_tree = Some(false);
+    let output = render(&config).unwrap();
+
+    assert!(output.starts_with("### Files:\n- main.rs:\n"));
+}
+
+#[test]
+fn tree_only_reads_no_files() {
+    let dir = fixture(&[("main.rs", b"fn main() {}\n")]);
+    let mut config = Config::new(dir.path());
+    config.tree_only = true;
+    config.prompt = Some("Where is main?".to_string());
+
+    assert!(collect_files(&config).unwrap().is_empty());
+    assert_eq!(
+        render(&config).unwrap(),
+        format!(
+            "### File Tree:\n{}\n└── main.rs\n\n\n### Prompt:\nWhere is main?\n",
+            dir.path().display()
+        )
+    );
+}
+
+#[test]
+fn deep_trees_do_not_overflow_the_stack() {
+    let dir = fixture(&[]);
+    let deep = (0..500).fold(dir.path().to_path_buf(), |path, _| path.join("d"));
+    fs::create_dir_all(&deep).unwrap();
+    fs::write(deep.join("leaf.txt"), "leaf").unwrap();
+    let config = Config::new(dir.path());
+
+    // Far less stack than the main thread's, each level used to take a frame
+    let output = std::thread::Builder::new()
+        .stack_size(256 * 1024)
+        .spawn(move || render(&config).unwrap())
+        .unwrap()
+        .join()
+        .unwrap();
+    assert!(output.contains("└── leaf.txt\n"));
+    assert!(output.contains("/leaf.txt:\n```\nleaf\n```\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn followed_symlink_cycles_are_walked_once() {
+    let dir = fixture(&[("a/file.txt", b"x")]);
+    std::os::unix::fs::symlink(dir.path().join("a"), dir.path().join("a/loop")).unwrap();
+    let mut config = Config::new(dir.path());
+    config.follow_symlinks = true;
+
+    assert_eq!(file_names(&config), ["a/file.txt"]);
+    assert!(!tree_string(&config).contains("loop"));
+}
+
+#[test]
+fn included_binary_files_are_base64_encoded() {
+    let dir = fixture(&[("logo.png", b"\x89PNG\r\n\x1a\n\0"), ("main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.include_binary = vec!["png".to_string()];
+    let output = render(&config).unwrap();
+
+    assert!(output.contains("- logo.png (image/png, base64):\n```base64\niVBORw0KGgoA\n```\n"));
+}
+
+#[test]
+fn trim_drops_surplus_blank_lines_and_trailing_whitespace() {
+    let dir = fixture(&[("a.py", b"\n\nx = 1   \n\n\n\n\ny = 2\n\n\nz = 3\n\n")]);
+    let mut config = Config::new(dir.path());
+    config.trim = true;
+    let files = collect_files(&config).unwrap();
+
+    assert_eq!(
+        files[0].content.as_deref(),
+        Some("x = 1\n\ny = 2\n\n\nz = 3\n")
+    );
+}
+
+#[test]
+fn language_filters_apply_to_tree_and_files() {
+    let dir = fixture(&[
+        ("src/main.rs", b""),
+        ("Cargo.toml", b""),
+        ("data.json", b"{}"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.only_languages = vec!["rust".to_string(), "toml".to_string()];
+
+    assert_eq!(file_names(&config), ["src/main.rs", "Cargo.toml"]);
+    assert!(!tree_string(&config).contains("data.json"));
+
+    config.only_languages.clear();
+    config.skip_languages = vec!["rust".to_string()];
+    assert_eq!(file_names(&config), ["Cargo.toml", "data.json"]);
+    assert!(!tree_string(&config).contains("src"));
+}
+
+#[test]
+fn name_excludes_match_at_any_depth() {
+    let dir = fixture(&[
+        ("node_modules/a.js", b""),
+        ("web/node_modules/b.js", b""),
+        ("web/app.js", b""),
+        ("web/debug.log", b""),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = patterns(&["node_modules", "*.log"]);
+
+    assert_eq!(file_names(&config), ["web/app.js"]);
+    assert!(!tree_string(&config).contains("node_modules"));
+}
+
+#[test]
+fn path_excludes_match_relative_to_the_directory() {
+    let dir = fixture(&[
+        ("src/gen/a.rs", b""),
+        ("lib/src/gen/b.rs", b""),
+        ("src/main.rs", b""),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = patterns(&["src/gen"]);
+    assert_eq!(file_names(&config), ["lib/src/gen/b.rs", "src/main.rs"]);
+
+    config.exclude_patterns = patterns(&["**/src/gen"]);
+    assert_eq!(file_names(&config), ["src/main.rs"]);
+
+    config.exclude_patterns = patterns(&["/lib"]);
+    assert_eq!(file_names(&config), ["src/gen/a.rs", "src/main.rs"]);
+}
+
+#[test]
+fn patterns_match_paths_with_either_separator() {
+    // A name ending in a dot, as `./` must only be dropped where it is a component
+    let dir = fixture(&[
+        ("v1./src/gen/a.rs", b""),
+        ("v1./src/main.rs", b""),
+        ("v1./b.toml", b""),
+    ]);
+    let root = dir.path().join("v1.").to_string_lossy().into_owned();
+    let mut directories = vec![format!("{}/./", root.replace('\\', "/"))];
+    if cfg!(windows) {
+        directories.push(format!("{}\\.\\", root.replace('/', "\\")));
+    }
+
+    for directory in directories {
+        let mut config = Config::new(&directory);
+        config.include_patterns = patterns(&["src/*.rs"]);
+        config.exclude_patterns = patterns(&["src/gen"]);
+        assert_eq!(file_names(&config), ["src/main.rs"], "{}", directory);
+    }
+}
+
+#[test]
+fn glob_paths_use_forward_slashes() {
+    assert_eq!(glob_path(Path::new("src\\gen\\a.rs")), "src/gen/a.rs");
+    assert_eq!(glob_path(Path::new(".\\src/./gen\\a.rs")), "src/gen/a.rs");
+    assert_eq!(glob_path(Path::new("./v1./a.rs")), "v1./a.rs");
+    assert_eq!(glob_path(Path::new("/abs/src\\a.rs")), "/abs/src/a.rs");
+    assert!(Pattern::new("src/*.rs")
+        .unwrap()
+        .matches(&glob_path(Path::new("src\\a.rs"))));
+}
+
+#[test]
+fn extra_ignore_files_are_merged() {
+    let dir = fixture(&[
+        (".dockerignore", b"*.log\n"),
+        ("web/.npmignore", b"dist\n"),
+        ("web/dist/bundle.js", b""),
+        ("web/app.js", b""),
+        ("debug.log", b""),
+        ("main.rs", b""),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.ignore_files = vec![
+        ".dockerignore".into(),
+        "web/.npmignore".into(),
+        "missing.ignore".into(),
+    ];
+
+    assert_eq!(file_names(&config), ["web/app.js", "main.rs"]);
+}
+
+#[test]
+fn tree_sizes_annotate_files_and_directories() {
+    let dir = fixture(&[
+        ("src/a.rs", b"12345"),
+        ("src/b.rs", b"123"),
+        ("c.txt", b"1"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.tree_sizes = true;
+
+    let expected = format!(
+        "{} (9B)\n\
+         ├── src/ (8B)\n\
+         │   ├── a.rs (5B)\n\
+         │   └── b.rs (3B)\n\
+         └── c.txt (1B)\n",
+        dir.path().display()
+    );
+    assert_eq!(tree_string(&config), expected);
+}
+
+#[test]
+fn size_range_filters_tree_and_files() {
+    let dir = fixture(&[
+        ("pkg/__init__.py", b""),
+        ("pkg/core.py", b"x = 1\n"),
+        ("big.json", b"[1, 2, 3, 4, 5, 6, 7, 8, 9]"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.min_size = Some(1);
+    config.max_size = Some(10);
+
+    assert_eq!(file_names(&config), ["pkg/core.py"]);
+    let tree = tree_string(&config);
+    assert!(!tree.contains("__init__.py"));
+    assert!(!tree.contains("big.json"));
+}
+
+#[test]
+fn cache_rereads_only_changed_files() {
+    let dir = fixture(&[("a.txt", b"one\n")]);
+    let cache = TempDir::new().unwrap();
+    let mut config = Config::new(dir.path());
+    config.cache_dir = Some(cache.path().to_path_buf());
+    let content = |config: &Config| collect_files(config).unwrap()[0].content.clone().unwrap();
+    assert_eq!(content(&config), "one\n");
+
+    // Same size and modification time, so the cached contents are used
+    let path = dir.path().join("a.txt");
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    fs::write(&path, b"two\n").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    assert_eq!(content(&config), "one\n");
+
+    fs::write(&path, b"three\n").unwrap();
+    assert_eq!(content(&config), "three\n");
+}
+
+#[test]
+fn toc_links_to_file_anchors() {
+    let dir = fixture(&[
+        ("src/main.rs", b"fn main() {}\n"),
+        ("a-b.txt", b"a"),
+        ("a_b.txt", b"b"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.toc = true;
+
+    let output = render(&config).unwrap();
+    assert!(output.contains(
+        "- [src/main.rs](#file-src-main-rs)\n\
+         - [a-b.txt](#file-a-b-txt)\n\
+         - [a_b.txt](#file-a-b-txt-1)\n\n"
+    ));
+    assert!(output.contains("<a id=\"file-src-main-rs\"></a>\n- src/main.rs:\n"));
+    assert!(output.contains("<a id=\"file-a-b-txt-1\"></a>\n- a_b.txt:\n"));
+}
+
+#[test]
+fn braces_expand_into_several_patterns() {
+    assert_eq!(
+        split_patterns("*.{png,jpg}, src/{a,b{1,2}}, {x}"),
+        ["*.png", "*.jpg", "src/a", "src/b1", "src/b2", "{x}"]
+    );
+
+    let dir = fixture(&[("a.png", b""), ("b.jpg", b""), ("c.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = split_patterns("*.{png,jpg}")
+        .iter()
+        .map(|pattern| Pattern::new(pattern).unwrap())
+        .collect();
+    assert_eq!(file_names(&config), ["c.rs"]);
+}
+
+#[test]
+fn quiet_leaves_only_fatal_errors() {
+    let dir = fixture(&[(".dockerignore", b"{a\n"), ("a.rs", b"fn a() {}\n")]);
+    assert!(TokenCounter::new("no-such-model").unwrap().estimated());
+    assert!(!TokenCounter::new("gpt-4").unwrap().estimated());
+
+    let run = |quiet: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_promptify"));
+        command.arg("-d").arg(dir.path()).args([
+            "--ignore-file",
+            ".dockerignore",
+            "-e",
+            "[,missing",
+            "--lang-map",
+            "nope",
+            "--count-tokens",
+            "no-such-model",
+        ]);
+        if quiet {
+            command.arg("--quiet");
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let stderr = run(false);
+    for warning in [
+        "Invalid glob pattern '['",
+        "Invalid language mapping 'nope'",
+        "Unknown model 'no-such-model'",
+        "Exclude pattern 'missing' matched nothing",
+        "Invalid rule in",
+    ] {
+        assert!(stderr.contains(warning), "{}", stderr);
+    }
+    assert!(!run(true).contains("Warning"));
+}
+
+#[test]
+fn warnings_show_paths_as_displayed() {
+    let dir = fixture(&[
+        ("sub/.dockerignore", b"{a\n"),
+        ("sub/bad.rs", b"fn \xff() {}\n"),
+    ]);
+    let output = Command::new(env!("CARGO_BIN_EXE_promptify"))
+        .arg("-d")
+        .arg(dir.path().join("sub"))
+        .arg("--relative-to")
+        .arg(dir.path())
+        .args(["--ignore-file", ".dockerignore", "--encoding", "skip"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: Skipping sub/bad.rs, which is not valid UTF-8\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Warning: Invalid rule in sub/.dockerignore: line 1: "),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn split_keeps_files_whole_within_the_budget() {
+    let word = "word ".repeat(100);
+    let dir = fixture(&[
+        ("a.txt", word.as_bytes()),
+        ("b.txt", word.as_bytes()),
+        ("c.txt", word.as_bytes()),
+    ]);
+    let config = Config::new(dir.path());
+    let counter = TokenCounter::new("gpt-4").unwrap();
+    let section = build_section(&config).unwrap();
+
+    let parts = split_by_tokens(&counter, 250, &config, vec![section]);
+    let files: Vec<usize> = parts.iter().map(|part| part[0].files.len()).collect();
+    // The tree takes room from the first part only
+    assert_eq!(files, [1, 2]);
+    let rendered = render_parts(&config, &parts);
+    assert!(rendered[0].starts_with("Part 1 of 2\n"));
+    for part in &rendered {
+        assert!(counter.count(part) <= 250);
+    }
+}
+
+#[test]
+fn relative_to_shows_paths_from_another_root() {
+    let dir = fixture(&[
+        ("sub/in/a.rs", b"fn a() {}\n"),
+        ("sub/b.rs", b"fn b() {}\n"),
+    ]);
+    let mut config = Config::new(dir.path().join("sub"));
+    config.relative_to = Some(dir.path().to_path_buf());
+
+    let output = render(&config).unwrap();
+    assert!(output.starts_with("### File Tree:\nsub\n├── in/\n"));
+    assert!(output.contains("- sub/in/a.rs:\n"));
+    assert!(output.contains("- sub/b.rs:\n"));
+
+    config.relative_to = Some(dir.path().join("sub/in"));
+    let output = render(&config).unwrap();
+    assert!(output.contains("- a.rs:\n"));
+    assert!(output.contains("- ../b.rs:\n"));
+
+    config.format = Format::Json;
+    let json: serde_json::Value = serde_json::from_str(&render(&config).unwrap()).unwrap();
+    let paths: Vec<&str> = json["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["a.rs", "../b.rs"]);
+}
+
+#[test]
+fn shebang_gives_extensionless_scripts_a_language() {
+    let dir = fixture(&[
+        ("deploy", b"#!/usr/bin/env -S python3.12 -u\nprint('hi')\n"),
+        ("build", b"#!/bin/sh\nmake\n"),
+        ("notes", b"just text\n"),
+    ]);
+    let config = Config::new(dir.path());
+
+    let languages: Vec<(String, String)> = collect_files(&config)
+        .unwrap()
+        .into_iter()
+        .map(|file| (name_in(dir.path(), &file.path), file.language))
+        .collect();
+    assert_eq!(
+        languages,
+        [
+            ("build".to_string(), "bash".to_string()),
+            ("deploy".to_string(), "python".to_string()),
+            ("notes".to_string(), String::new()),
+        ]
+    );
+}
+
+#[test]
+fn archive_holds_files_and_document() {
+    let dir = fixture(&[("src/main.rs", b"fn main() {}\n"), ("README.md", b"# Hi\n")]);
+    let mut config = Config::new(dir.path());
+    config.prompt = Some("Review this".to_string());
+    let section = build_section(&config).unwrap();
+
+    let mut buffer = io::Cursor::new(Vec::new());
+    assert_eq!(write_archive(&config, &[section], &mut buffer).unwrap(), 2);
+
+    let mut archive = zip::ZipArchive::new(buffer).unwrap();
+    let names: Vec<String> = (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["promptify.md", "src/main.rs", "README.md"]);
+    let mut document = String::new();
+    archive
+        .by_name("promptify.md")
+        .unwrap()
+        .read_to_string(&mut document)
+        .unwrap();
+    assert!(document.contains("└── README.md\n"));
+    assert!(document.ends_with("Review this\n"));
+}
+
+#[test]
+fn archive_keeps_files_from_above_relative_to_apart() {
+    let dir = fixture(&[
+        ("a.rs", b"outer"),
+        ("src/a.rs", b"inner"),
+        ("promptify.md", b"notes"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.relative_to = Some(dir.path().join("src"));
+    let section = build_section(&config).unwrap();
+
+    let mut buffer = io::Cursor::new(Vec::new());
+    assert_eq!(write_archive(&config, &[section], &mut buffer).unwrap(), 3);
+
+    let mut archive = zip::ZipArchive::new(buffer).unwrap();
+    let mut entry = |name: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(entry("a.rs"), "inner");
+    assert_eq!(entry("a.rs (2)"), "outer");
+    assert_eq!(entry("promptify.md (2)"), "notes");
+}
+
+#[test]
+fn scope_limits_the_walk_to_a_prefix() {
+    let dir = fixture(&[
+        ("packages/foo/src/a.rs", b"a"),
+        ("packages/bar/b.rs", b"b"),
+        ("packages/x.rs", b"x"),
+        ("README.md", b"r"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.scopes = vec!["packages/foo".into()];
+
+    assert_eq!(file_names(&config), ["packages/foo/src/a.rs"]);
+    let expected = format!(
+        "{}\n\
+         └── packages/\n    \
+             └── foo/\n        \
+                 └── src/\n            \
+                     └── a.rs\n",
+        dir.path().display()
+    );
+    assert_eq!(tree_string(&config), expected);
+}
+
+#[test]
+fn follow_imports_keeps_only_referenced_files() {
+    let dir = fixture(&[
+        ("web/app.ts", b"import { a } from './lib/a';\nimport './side.js';\nconst b = require(\"../shared\");\nimport React from 'react';\n"),
+        ("web/lib/a.ts", b"export * from './index';\n"),
+        ("web/lib/index.tsx", b""),
+        ("web/side.js", b""),
+        ("web/unused.ts", b""),
+        ("shared/index.js", b""),
+        ("Cargo.toml", b"[package]\nname = \"my-app\"\n"),
+        ("src/main.rs", b"mod cli;\nuse my_app::Config;\n"),
+        ("src/cli/mod.rs", b"use super::{config::Config, other as o};\n"),
+        ("src/config.rs", b""),
+        ("src/other.rs", b""),
+        ("src/lib.rs", b"pub mod config;\n"),
+        ("src/unused.rs", b""),
+    ]);
+
+    let files = |entry: &str| -> Vec<String> {
+        follow_imports(dir.path(), &[entry.into()])
+            .unwrap()
+            .iter()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect()
+    };
+    assert_eq!(
+        files("web/app.ts"),
+        [
+            "web/app.ts",
+            "web/lib/a.ts",
+            "web/lib/index.tsx",
+            "web/side.js",
+            "shared/index.js"
+        ]
+    );
+    assert_eq!(
+        files("src/main.rs"),
+        [
+            "src/main.rs",
+            "src/cli/mod.rs",
+            "src/config.rs",
+            "src/other.rs",
+            "src/lib.rs"
+        ]
+    );
+}
+
+#[test]
+fn long_tables_keep_header_and_first_rows() {
+    let mut csv = String::from("id,name\n");
+    for i in 0..50 {
+        csv.push_str(&format!("{},n{}\n", i, i));
+    }
+    let dir = fixture(&[("data.csv", csv.as_bytes()), ("small.tsv", b"a\tb\n1\t2\n")]);
+    let mut config = Config::new(dir.path());
+    config.csv_rows = Some(2);
+
+    let files = collect_files(&config).unwrap();
+    assert_eq!(
+        files[0].content.as_deref(),
+        Some("id,name\n0,n0\n1,n1\n// 48 more rows, 50 in total\n")
+    );
+    assert_eq!(files[1].content.as_deref(), Some("a\tb\n1\t2\n"));
+}
+
+#[test]
+fn order_sorts_the_listing() {
+    let dir = fixture(&[
+        ("z/small.rs", b"1"),
+        ("big.txt", b"1234567890"),
+        ("mid.md", b"12345"),
+    ]);
+    let mut config = Config::new(dir.path());
+    assert_eq!(file_names(&config), ["z/small.rs", "big.txt", "mid.md"]);
+
+    config.order = Order::Alpha;
+    assert_eq!(file_names(&config), ["big.txt", "mid.md", "z/small.rs"]);
+    config.order = Order::Size;
+    assert_eq!(file_names(&config), ["big.txt", "mid.md", "z/small.rs"]);
+    config.order = Order::Type;
+    assert_eq!(file_names(&config), ["mid.md", "z/small.rs", "big.txt"]);
+}
+
+#[test]
+fn unmatched_excludes_are_reported() {
+    let dir = fixture(&[("node_modules/x.js", b""), ("a.log", b""), ("main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = patterns(&["node_module", "*.log", "node_modules", "*.log"]);
+
+    let section = build_section(&config).unwrap();
+    assert_eq!(section.unmatched_excludes, ["node_module"]);
+}
+
+#[test]
+fn manifest_hashes_included_files() {
+    let dir = fixture(&[("src/lib.rs", b"abc"), ("image.png", b"\x89PNG\0")]);
+    let config = Config::new(dir.path());
+    let section = build_section(&config).unwrap();
+
+    let manifest = build_manifest(&config, &[section]);
+    assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(manifest.files.len(), 1);
+    let file = &manifest.files[0];
+    assert_eq!(file.path, "src/lib.rs");
+    assert_eq!(file.size, 3);
+    assert_eq!(
+        file.sha256,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(file.language, "rust");
+}
+
+#[test]
+fn manifest_folders_only_paths_relative_to_each_directory() {
+    let dir = fixture(&[("api/src/a.rs", b"a"), ("web/src/b.js", b"b")]);
+    let mut config = Config::new(dir.path().join("api"));
+    let api = build_section(&config).unwrap();
+    config.directory = dir.path().join("web");
+    let web = build_section(&config).unwrap();
+    let sections = [api, web];
+
+    let paths = |config: &Config| -> Vec<String> {
+        let manifest = build_manifest(config, &sections);
+        manifest.files.into_iter().map(|file| file.path).collect()
+    };
+    assert_eq!(paths(&config), ["api/src/a.rs", "web/src/b.js"]);
+    config.relative_to = Some(dir.path().to_path_buf());
+    assert_eq!(paths(&config), ["api/src/a.rs", "web/src/b.js"]);
+}
+
+#[test]
+fn strip_comments_keeps_shell_parameter_expansions() {
+    let dir = fixture(&[(
+        "count.sh",
+        b"# Counts the arguments\narr=(a b)\necho ${#arr[@]} # items\necho $# ${#1}\n",
+    )]);
+    let mut config = Config::new(dir.path());
+    config.strip_comments = true;
+
+    let files = collect_files(&config).unwrap();
+    assert_eq!(
+        files[0].content.as_deref(),
+        Some("arr=(a b)\necho ${#arr[@]}\necho $# ${#1}\n")
+    );
+}
+
+#[test]
+fn minify_compacts_json_and_yaml() {
+    let dir = fixture(&[
+        ("a.json", b"{\n  \"b\": \"x y\",\n  \"a\": [1, 2.50]\n}\n"),
+        ("b.yaml", b"# comment\nname: x\nlist:\n  - 1\n  - two\n"),
+        ("c.json", b"{ not json\n"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.minify = true;
+
+    let contents: Vec<String> = collect_files(&config)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.content.unwrap())
+        .collect();
+    assert_eq!(
+        contents,
+        [
+            "{\"b\":\"x y\",\"a\":[1,2.50]}\n",
+            "{\"name\":\"x\",\"list\":[1,\"two\"]}\n",
+            "{ not json\n",
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn transforms_pipe_matching_files_through_commands() {
+    let dir = fixture(&[
+        ("a.txt", b"keep\n"),
+        ("q/b.sql", b"select 1;\n"),
+        ("q/c.sql", b"select 2;\n"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.transforms = vec![
+        "q/c.sql=false".parse().unwrap(),
+        "*.sql = tr a-z A-Z".parse().unwrap(),
+    ];
+    assert!("*.sql".parse::<Transform>().is_err());
+
+    let contents: Vec<String> = collect_files(&config)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.content.unwrap())
+        .collect();
+    // A failing command keeps the file as it is
+    assert_eq!(contents, ["SELECT 1;\n", "select 2;\n", "keep\n"]);
+}
+
+#[test]
+fn line_ranges_keep_only_the_listed_lines() {
+    let dir = fixture(&[("main.rs", b"one\ntwo\nthree\nfour\nfive\n")]);
+    let (path, ranges) = parse_file_spec("main.rs:2-3,5").unwrap();
+    assert_eq!(path, Path::new("main.rs"));
+    assert_eq!(parse_file_spec("a:b.rs").unwrap().1, []);
+    assert!(parse_file_spec("main.rs:3-2").is_err());
+
+    let mut config = Config::new(dir.path());
+    config.files = Some(vec![path.clone()]);
+    config.line_ranges.insert(path, ranges);
+    config.line_numbers = Some(0);
+
+    let files = collect_files(&config).unwrap();
+    assert_eq!(
+        files[0].content.as_deref(),
+        Some("2 | two\n3 | three\n...\n5 | five\n")
+    );
+    assert!(render(&config)
+        .unwrap()
+        .contains("- main.rs (lines 2-3, 5):"));
+}
+
+#[test]
+fn max_files_fails_before_reading() {
+    let dir = fixture(&[("a.rs", b""), ("b.rs", b""), ("c/d.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.max_files = Some(3);
+    assert_eq!(collect_files(&config).unwrap().len(), 3);
+
+    config.max_files = Some(2);
+    let err = collect_files(&config).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("More than 2 files would be included"));
+}
+
+#[test]
+fn lockfiles_stay_in_the_tree_only() {
+    let dir = fixture(&[
+        ("Cargo.lock", b"# generated"),
+        ("web/yarn.lock", b""),
+        ("main.rs", b""),
+    ]);
+    let mut config = Config::new(dir.path());
+    assert_eq!(file_names(&config), ["main.rs"]);
+    assert!(render(&config).unwrap().contains("Cargo.lock"));
+
+    config.include_lockfiles = true;
+    assert_eq!(
+        file_names(&config),
+        ["web/yarn.lock", "Cargo.lock", "main.rs"]
+    );
+}
+
+#[test]
+fn color_styles_directories_in_the_tree() {
+    let dir = fixture(&[("src/main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    let plain = render_tree(&config, &build_tree(&config).unwrap());
+    assert!(!plain.contains('\x1b'));
+
+    config.color = true;
+    let colored = render_tree(&config, &build_tree(&config).unwrap());
+    assert!(colored.contains("\x1b[1;34msrc/\x1b[0m\n"));
+    assert!(colored.ends_with("\x1b[0mmain.rs\n"));
+}
+
+#[test]
+fn render_to_writer_matches_render() {
+    let dir = fixture(&[("src/main.rs", b"fn main() {}\n"), ("README.md", b"# Hi\n")]);
+    let mut config = Config::new(dir.path());
+    config.prompt = Some("Explain".to_string());
+    for format in [
+        Format::Markdown,
+        Format::Json,
+        Format::Plain,
+        Format::Xml,
+        Format::Html,
+    ] {
+        config.format = format;
+        let mut streamed = Vec::new();
+        render_to_writer(&config, &mut streamed).unwrap();
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            render(&config).unwrap()
+        );
+    }
+}
+
+#[test]
+fn files_that_vanish_before_reading_keep_the_output_clean() {
+    let dir = fixture(&[("a.txt", b"kept"), ("b.txt", b"gone")]);
+    let mut config = Config::new(dir.path());
+    config.show_tree = Some(false);
+    let mut section = build_section(&config).unwrap();
+    let paths = select_files(&config).unwrap();
+    fs::remove_file(dir.path().join("b.txt")).unwrap();
+
+    section.files = read_files(&config, paths.clone(), |_| {});
+    assert_eq!(
+        render_sections(&config, std::slice::from_ref(&section)),
+        "### Files:\n- a.txt:\n```\nkept\n```\n\n- b.txt:\n```\n// <error reading file>\n```\n\n"
+    );
+
+    config.no_content_on_error = true;
+    section.files = read_files(&config, paths, |_| {});
+    assert_eq!(
+        render_sections(&config, &[section]),
+        "### Files:\n- a.txt:\n```\nkept\n```\n\n"
+    );
+}
+
+#[test]
+fn templates_label_the_files_of_each_directory() {
+    let dir = fixture(&[("api/src/a.rs", b"fn a() {}\n"), ("web/src/b.js", b"b()\n")]);
+    let mut config = Config::new(dir.path().join("web"));
+    let web = build_section(&config).unwrap();
+    config.directory = dir.path().join("api");
+    let api = build_section(&config).unwrap();
+
+    let rendered = render_template("{{files}}", &config, &[api, web]).unwrap();
+    assert!(rendered.contains("- src/a.rs:\n"));
+    assert!(rendered.contains("- src/b.js:\n"), "{}", rendered);
+}
+
+#[test]
+fn text_section_wraps_piped_text_in_a_fence() {
+    let mut config = Config::new("");
+    config.show_tree = Some(false);
+    config.line_numbers = Some(0);
+    config.prompt = Some("Explain".to_string());
+
+    let section = text_section(&config, "stdin", "rust", "fn main() {}".to_string());
+    assert_eq!(
+        render_sections(&config, &[section]),
+        "### Files:\n- stdin:\n```rust\n1 | fn main() {}\n```\n\n\n\n### Prompt:\nExplain\n"
+    );
+}
+
+#[test]
+fn line_counts_head_each_file() {
+    let dir = fixture(&[("a.rs", b"fn a() {}\n\nfn b() {}\n"), ("b.txt", b"one")]);
+    let mut config = Config::new(dir.path());
+    config.line_counts = true;
+    config.truncate_lines = Some(1);
+
+    let output = render(&config).unwrap();
+    assert!(output.contains("- a.rs:\n// 3 lines\n```rust\n"));
+    assert!(output.contains("- b.txt:\n// 1 line\n```\n"));
+}
+
+#[test]
+fn jobs_keep_the_output_order() {
+    let files: Vec<(String, Vec<u8>)> = (0..20)
+        .map(|i| (format!("f{:02}.txt", i), i.to_string().into_bytes()))
+        .collect();
+    let files: Vec<(&str, &[u8])> = files
+        .iter()
+        .map(|(path, content)| (path.as_str(), content.as_slice()))
+        .collect();
+    let dir = fixture(&files);
+    let mut config = Config::new(dir.path());
+    let expected = render(&config).unwrap();
+
+    config.jobs = Some(1);
+    assert_eq!(render(&config).unwrap(), expected);
+}
+
+#[test]
+fn strip_prefix_shortens_shown_paths() {
+    let dir = fixture(&[("app/src/main.rs", b"fn main() {}\n")]);
+    let mut config = Config::new(dir.path().join("app"));
+    config.relative_to = Some(dir.path().to_path_buf());
+    config.strip_prefix = Some("app".to_string());
+
+    let output = render(&config).unwrap();
+    assert!(output.contains("### File Tree:\n.\n└── src/\n"));
+    assert!(output.contains("- src/main.rs:\n"));
+
+    config.format = Format::Json;
+    let json: serde_json::Value = serde_json::from_str(&render(&config).unwrap()).unwrap();
+    assert_eq!(json["files"][0]["path"], "src/main.rs");
+
+    // Paths are shown relative to the directory, then stripped, as in markdown
+    config.relative_to = None;
+    config.strip_prefix = Some("src".to_string());
+    let json: serde_json::Value = serde_json::from_str(&render(&config).unwrap()).unwrap();
+    assert_eq!(json["files"][0]["path"], "main.rs");
+
+    config.format = Format::Markdown;
+    config.relative_to = Some(dir.path().to_path_buf());
+    config.strip_prefix = Some("elsewhere/".to_string());
+    assert!(render(&config).unwrap().contains("- app/src/main.rs:\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_directories_are_marked_in_the_tree() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture(&[("locked/secret.txt", b"x"), ("ok/a.txt", b"hi")]);
+    let locked = dir.path().join("locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    // Root reads it regardless, so there is nothing to check
+    if fs::read_dir(&locked).is_ok() {
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+
+    let config = Config::new(dir.path());
+    let tree = build_tree(&config);
+    let files = collect_files(&config);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let tree = render_tree(&config, &tree.unwrap());
+    assert!(tree.contains("├── locked/ <permission denied>\n"));
+    assert_eq!(files.unwrap().len(), 1);
+}
+
+#[test]
+fn flatten_labels_files_by_name() {
+    let dir = fixture(&[("a/mod.rs", b""), ("b/mod.rs", b""), ("src/main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.flatten = true;
+    config.show_tree = Some(false);
+
+    let labels: Vec<String> = render(&config)
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("- "))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(labels, ["- mod.rs:", "- mod.rs (2):", "- main.rs:"]);
+}
+
+#[test]
+fn byte_order_marks_are_dropped_and_utf16_decoded() {
+    let utf16le: Vec<u8> = [0xFF, 0xFE]
+        .into_iter()
+        .chain("héllo\n".encode_utf16().flat_map(u16::to_le_bytes))
+        .collect();
+    let utf16be: Vec<u8> = [0xFE, 0xFF]
+        .into_iter()
+        .chain("wörld\n".encode_utf16().flat_map(u16::to_be_bytes))
+        .collect();
+    let dir = fixture(&[
+        ("a.txt", b"\xEF\xBB\xBFfirst line\n"),
+        ("b.txt", &utf16le),
+        ("c", &utf16be),
+    ]);
+    let config = Config::new(dir.path());
+
+    let contents: Vec<String> = collect_files(&config)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.content.unwrap())
+        .collect();
+    assert_eq!(contents, ["first line\n", "héllo\n", "wörld\n"]);
+}
+
+#[test]
+fn summary_only_keeps_declarations() {
+    let dir = fixture(&[
+        (
+            "lib.rs",
+            b"pub struct Point {\n    x: i32,\n}\n\nimpl Point {\n    /// Brace in a string: '{'\n    pub fn new() -> Self {\n        Point { x: '{' as i32 }\n    }\n}\n\nfn helper(\n    a: u32,\n) -> u32 {\n    a\n}\n",
+        ),
+        (
+            "app.py",
+            b"import os\n\nclass App:\n    def run(self):\n        def inner():\n            pass\n        return 1\n\ndef main(): App().run()\n",
+        ),
+        ("notes.txt", b"kept as is\n"),
+        // Cut off in an unclosed raw string
+        ("raw.rs", b"fn a() {}\nconst S: &str = r\""),
+        ("raw_hashes.rs", b"fn b() {}\nconst S: &str = r#\""),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.summary_only = true;
+
+    let contents: Vec<String> = collect_files(&config)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.content.unwrap())
+        .collect();
+    assert_eq!(
+        contents,
+        [
+            "class App:\n    def run(self): ...\ndef main(): ...\n",
+            "pub struct Point {\n    x: i32,\n}\nimpl Point {\n    pub fn new() -> Self { ... }\n}\nfn helper(a: u32) -> u32 { ... }\n",
+            "kept as is\n",
+            "fn a() { ... }\nconst S: &str = ...;\n",
+            "fn b() { ... }\nconst S: &str = ...;\n",
+        ]
+    );
+}
+
+#[test]
+fn excluded_entries_are_counted_by_reason() {
+    let dir = fixture(&[
+        ("node_modules/a/index.js", b"12345"),
+        ("node_modules/b.js", b"123"),
+        (".env", b"KEY=1"),
+        ("logo.png", b"\x89PNG"),
+        ("main.rs", b"fn main() {}\n"),
+    ]);
+    let mut config = Config::new(dir.path());
+    config.exclude_patterns = patterns(&["node_modules"]);
+    config.show_excluded_summary = true;
+
+    let section = build_section(&config).unwrap();
+    let mut excluded: Vec<(ExclusionReason, usize, u64)> = section
+        .excluded
+        .iter()
+        .map(|excluded| (excluded.reason, excluded.files, excluded.bytes))
+        .collect();
+    excluded.sort();
+    assert_eq!(
+        excluded,
+        [
+            (ExclusionReason::Pattern, 2, 8),
+            (ExclusionReason::Hidden, 1, 5),
+            (ExclusionReason::Binary, 1, 4),
+        ]
+    );
+}
+
+#[test]
+fn html_escapes_and_highlights_files() {
+    let dir = fixture(&[("src/main.rs", b"// <b>\nfn main() { let s = \"a & b\"; }\n")]);
+    let mut config = Config::new(dir.path());
+    config.format = Format::Html;
+    config.prompt = Some("Review & fix".to_string());
+
+    let html = render(&config).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>\n"));
+    assert!(html.contains("<li><span class=\"dir\">src/</span>\n<ul>\n<li>main.rs</li>\n</ul>"));
+    assert!(html.contains(
+        "<pre><code class=\"language-rust\"><span class=\"comment\">// &lt;b&gt;</span>\n\
+         <span class=\"kw\">fn</span> main() { <span class=\"kw\">let</span> s = \
+         <span class=\"str\">&quot;a &amp; b&quot;</span>; }\n</code></pre>"
+    ));
+    assert!(html.contains("<pre class=\"prompt\">Review &amp; fix</pre>"));
+    assert!(html.ends_with("</body>\n</html>\n"));
+}
+
+#[test]
+fn tree_items_know_files_from_directories() {
+    let dir = fixture(&[("empty/", b""), ("src/main.rs", b"")]);
+    let mut config = Config::new(dir.path());
+    config.keep_empty_dirs = true;
+
+    let tree = build_tree(&config).unwrap();
+    let kinds: Vec<(&str, Kind)> = tree
+        .children
+        .iter()
+        .map(|child| (child.name.as_str(), child.kind))
+        .collect();
+    assert_eq!(kinds, [("empty", Kind::Dir), ("src", Kind::Dir)]);
+    assert_eq!(tree.kind, Kind::Dir);
+    assert_eq!(tree.children[1].children[0].kind, Kind::File);
+
+    config.files = Some(vec!["src/main.rs".into()]);
+    let tree = build_tree(&config).unwrap();
+    assert_eq!(tree.children[0].kind, Kind::Dir);
+    assert_eq!(tree.children[0].children[0].kind, Kind::File);
+}
+
+#[test]
+fn watch_snapshots_notice_changes_but_not_written_files() {
+    let dir = fixture(&[
+        ("a.rs", b"fn a() {}\n"),
+        ("empty/", b""),
+        ("out.md", b""),
+        ("m.json", b""),
+    ]);
+    let mut config = Config::new(dir.path());
+    for written in ["out.md", "m.json"] {
+        let pattern = written_pattern(dir.path(), &dir.path().join(written)).unwrap();
+        config.exclude_patterns.push(pattern);
+    }
+    let configs = [config];
+
+    // Rewriting the output and the manifest is no change
+    let snapshot = Snapshot::take(&configs);
+    fs::write(dir.path().join("out.md"), "rendered").unwrap();
+    fs::write(dir.path().join("m.json"), "{}").unwrap();
+    assert!(!snapshot.changed());
+
+    fs::write(dir.path().join("a.rs"), "fn b() {}\n").unwrap();
+    assert!(snapshot.changed());
+
+    // New files are noticed through their directory, even an empty one
+    let snapshot = Snapshot::take(&configs);
+    fs::write(dir.path().join("empty/b.rs"), "").unwrap();
+    assert!(snapshot.changed());
+
+    let snapshot = Snapshot::take(&configs);
+    fs::remove_file(dir.path().join("a.rs")).unwrap();
+    assert!(snapshot.changed());
+}
+
+#[test]
+fn watch_waits_for_a_burst_of_changes_to_settle() {
+    let dir = fixture(&[("a.rs", b"")]);
+    let configs = [Config::new(dir.path())];
+    let snapshot = Snapshot::take(&configs);
+    let done = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 0..30 {
+                fs::write(dir.path().join("a.rs"), i.to_string()).unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+        let settled = wait_for_change(&configs, snapshot, Duration::from_millis(200));
+        assert!(done.load(Ordering::SeqCst));
+        assert!(!settled.changed());
+    });
+}